@@ -2,7 +2,6 @@ use crate::models::*;
 use num_traits::{Bounded, Num};
 use std::cmp::Reverse;
 use std::collections::BinaryHeap;
-use std::ops::Index;
 
 pub trait Graph<T> {
     fn get_cost(&self, edge: EdgeIndex) -> T;
@@ -75,3 +74,13 @@ pub fn compute_shortest_path<G: Graph<T>, T: Bounded + Num + Copy + PartialOrd>(
     path.reverse();
     (path, dist[dest])
 }
+
+pub fn compute_path_cost<G: Graph<T>, T: Num + Copy>(graph: &G, src: Pos, path: &[Dir]) -> T {
+    let mut cost = T::zero();
+    let mut p = src;
+    for &dir in path {
+        cost = cost + graph.get_cost(EdgeIndex::from_move(p, dir));
+        p = p.move_to(dir).unwrap();
+    }
+    cost
+}
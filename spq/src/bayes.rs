@@ -0,0 +1,279 @@
+use crate::algorithms::Graph;
+use crate::models::*;
+use crate::params::SolverParams;
+use crate::policy::standard_normal;
+use crate::solver::CostEstimator;
+use rand::Rng;
+
+const PARTS: usize = 2;
+const NUM_SEGMENTS: usize = NUM_LINES * PARTS;
+const SPLIT_X: u8 = GRID_LEN as u8 / 2;
+// Variance of the response factor, uniform in [0.9, 1.1]
+const RESPONSE_FACTOR_VAR: f64 = 0.2 * 0.2 / 12.0;
+
+fn segment_of(edge: EdgeIndex) -> usize {
+    edge.line.as_usize() * PARTS + if edge.x < SPLIT_X { 0 } else { 1 }
+}
+
+/// Gaussian posterior over edge costs, updated after each response.
+///
+/// `cost(edge) = segment[line, part] + deviation[edge]` where each line is split
+/// into two fixed halves. Segment costs keep a full covariance so that a
+/// response informs every line on the path jointly; edge deviations are kept
+/// independent of each other and of the segments (assumed density filtering).
+/// The response noise is multiplicative, so its variance is scaled by the
+/// square of the predicted length.
+pub struct BayesEstimator {
+    segment_mean: Vec<f64>,
+    // row-major NUM_SEGMENTS x NUM_SEGMENTS
+    segment_cov: Vec<f64>,
+    deviation_mean: GridGraph<f64>,
+    deviation_var: GridGraph<f64>,
+    records: Vec<(Vec<EdgeIndex>, f64)>,
+    loss: f64,
+}
+
+impl Graph<u32> for BayesEstimator {
+    fn get_cost(&self, edge: EdgeIndex) -> u32 {
+        self.edge_posterior(edge).0.round().max(1.0) as u32
+    }
+}
+
+impl BayesEstimator {
+    /// Posterior mean and variance of the cost of `edge`
+    pub fn edge_posterior(&self, edge: EdgeIndex) -> (f64, f64) {
+        let s = segment_of(edge);
+        (
+            self.segment_mean[s] + self.deviation_mean[edge],
+            self.segment_cov[s * NUM_SEGMENTS + s] + self.deviation_var[edge],
+        )
+    }
+
+    /// Posterior mean and variance of the base cost of `line` in `part` (0: x < 15, 1: x >= 15)
+    pub fn segment_posterior(&self, line: LineIndex, part: usize) -> (f64, f64) {
+        let s = line.as_usize() * PARTS + part;
+        (self.segment_mean[s], self.segment_cov[s * NUM_SEGMENTS + s])
+    }
+
+    // Lower triangular L with L L^T = segment_cov. Pivots that numerical
+    // error pushed below zero are treated as zero.
+    fn segment_cov_cholesky(&self) -> Vec<f64> {
+        let n = NUM_SEGMENTS;
+        let mut l = vec![0.0; n * n];
+        for i in 0..n {
+            for j in 0..=i {
+                let mut sum = self.segment_cov[i * n + j];
+                for k in 0..j {
+                    sum -= l[i * n + k] * l[j * n + k];
+                }
+                if i == j {
+                    l[i * n + i] = sum.max(0.0).sqrt();
+                } else if l[j * n + j] > 0.0 {
+                    l[i * n + j] = sum / l[j * n + j];
+                }
+            }
+        }
+        l
+    }
+
+    fn predict(&self, visited: &[EdgeIndex]) -> f64 {
+        visited.iter().map(|&e| self.edge_posterior(e).0).sum()
+    }
+
+    fn update(&mut self, visited: &[EdgeIndex], response: f64) {
+        let mut h = vec![0.0; NUM_SEGMENTS];
+        let mut segments = Vec::new();
+        for &edge in visited {
+            let s = segment_of(edge);
+            if h[s] == 0.0 {
+                segments.push(s);
+            }
+            h[s] += 1.0;
+        }
+
+        // P h
+        let mut ph = vec![0.0; NUM_SEGMENTS];
+        for (i, v) in ph.iter_mut().enumerate() {
+            let row = &self.segment_cov[i * NUM_SEGMENTS..(i + 1) * NUM_SEGMENTS];
+            *v = segments.iter().map(|&s| row[s] * h[s]).sum();
+        }
+        let hph: f64 = segments.iter().map(|&s| h[s] * ph[s]).sum();
+        let deviation_var: f64 = visited.iter().map(|&e| self.deviation_var[e]).sum();
+        let predicted = self.predict(visited);
+        let noise_var = RESPONSE_FACTOR_VAR * predicted * predicted;
+        let s = hph + deviation_var + noise_var;
+        let residual = response - predicted;
+
+        for (mean, &p) in self.segment_mean.iter_mut().zip(&ph) {
+            *mean += p * residual / s;
+        }
+        for i in 0..NUM_SEGMENTS {
+            if ph[i] == 0.0 {
+                continue;
+            }
+            let row = &mut self.segment_cov[i * NUM_SEGMENTS..(i + 1) * NUM_SEGMENTS];
+            for (j, v) in row.iter_mut().enumerate() {
+                *v -= ph[i] * ph[j] / s;
+            }
+        }
+        for &edge in visited {
+            let var = self.deviation_var[edge];
+            self.deviation_mean[edge] += var * residual / s;
+            self.deviation_var[edge] -= var * var / s;
+        }
+    }
+}
+
+impl CostEstimator for BayesEstimator {
+    fn new(params: &SolverParams) -> Self {
+        let mean = (params.line_cost_lb + params.line_cost_ub) as f64 / 2.0;
+        let var = params.bayes_line_sd * params.bayes_line_sd;
+        let mut segment_cov = vec![0.0; NUM_SEGMENTS * NUM_SEGMENTS];
+        for line in 0..NUM_LINES {
+            for a in 0..PARTS {
+                for b in 0..PARTS {
+                    let (i, j) = (line * PARTS + a, line * PARTS + b);
+                    segment_cov[i * NUM_SEGMENTS + j] = if a == b {
+                        var
+                    } else {
+                        var * params.bayes_part_corr
+                    };
+                }
+            }
+        }
+        BayesEstimator {
+            segment_mean: vec![mean; NUM_SEGMENTS],
+            segment_cov,
+            deviation_mean: GridGraph::new(0.0),
+            deviation_var: GridGraph::new(params.bayes_edge_sd * params.bayes_edge_sd),
+            records: Vec::new(),
+            loss: 0.0,
+        }
+    }
+
+    fn insert_new_record(&mut self, query: &Query, path: &[Dir], response: u32) {
+        let mut visited = Vec::with_capacity(path.len());
+        let mut cur = query.src;
+        for &dir in path {
+            visited.push(EdgeIndex::from_move(cur, dir));
+            cur = cur.move_to(dir).unwrap();
+        }
+        assert!(cur == query.dest);
+
+        self.update(&visited, response as f64);
+        self.records.push((visited, response as f64));
+
+        self.loss = self
+            .records
+            .iter()
+            .map(|(visited, response)| (self.predict(visited) - response).powi(2))
+            .sum();
+        trace!("Finish updating estimation. loss={:.0}", self.loss);
+    }
+
+    fn loss(&self) -> f64 {
+        self.loss
+    }
+
+    fn cost_variance(&self, edge: EdgeIndex) -> Option<f64> {
+        Some(self.edge_posterior(edge).1)
+    }
+
+    /// Samples segment costs jointly from their full covariance
+    fn sample_costs<R: Rng>(&self, rng: &mut R) -> Option<GridGraph<u32>> {
+        let l = self.segment_cov_cholesky();
+        let z: Vec<f64> = (0..NUM_SEGMENTS).map(|_| standard_normal(rng)).collect();
+        let segments: Vec<f64> = (0..NUM_SEGMENTS)
+            .map(|i| {
+                let row = &l[i * NUM_SEGMENTS..i * NUM_SEGMENTS + i + 1];
+                self.segment_mean[i] + row.iter().zip(&z).map(|(a, b)| a * b).sum::<f64>()
+            })
+            .collect();
+
+        let mut graph = GridGraph::new(0);
+        for edge in EdgeIndex::iter() {
+            let deviation = self.deviation_mean[edge]
+                + self.deviation_var[edge].max(0.0).sqrt() * standard_normal(rng);
+            let cost = segments[segment_of(edge)] + deviation;
+            graph[edge] = cost.round().max(1.0) as u32;
+        }
+        Some(graph)
+    }
+
+    fn log_summary(&self) {
+        debug!("segment_mean={:?}", self.segment_mean);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-6 * b.abs().max(1.0), "{} != {}", a, b);
+    }
+
+    #[test]
+    fn posterior_of_two_edges() {
+        let params = SolverParams::default();
+        let mut estimator = BayesEstimator::new(&params);
+        let line = LineIndex::new(Axis::Horizontal, 3);
+        let (a, b) = (EdgeIndex::new(line, 0), EdgeIndex::new(line, 1));
+        let (m, v) = estimator.edge_posterior(a);
+        let line_var = params.bayes_line_sd.powi(2);
+        let edge_var = params.bayes_edge_sd.powi(2);
+        assert_close(v, line_var + edge_var);
+
+        // both edges in the first half: h = 2 on one segment
+        let response = 2.0 * m + 1000.0;
+        estimator.update(&[a, b], response);
+        let predicted = 2.0 * m;
+        let s = 4.0 * line_var + 2.0 * edge_var + RESPONSE_FACTOR_VAR * predicted * predicted;
+        let residual = response - predicted;
+        for &edge in &[a, b] {
+            let (mean, var) = estimator.edge_posterior(edge);
+            assert_close(mean, m + (2.0 * line_var + edge_var) * residual / s);
+            assert_close(
+                var,
+                line_var - (2.0 * line_var).powi(2) / s + edge_var - edge_var.powi(2) / s,
+            );
+        }
+        // the other half of the line moves through the prior correlation
+        let (mean, var) = estimator.segment_posterior(line, 1);
+        let cov = line_var * params.bayes_part_corr;
+        assert_close(mean, m + 2.0 * cov * residual / s);
+        assert_close(var, line_var - (2.0 * cov).powi(2) / s);
+        // other lines are independent
+        let other = LineIndex::new(Axis::Vertical, 3);
+        assert_close(estimator.segment_posterior(other, 0).1, line_var);
+    }
+
+    #[test]
+    fn cholesky_of_prior() {
+        let estimator = BayesEstimator::new(&SolverParams::default());
+        let l = estimator.segment_cov_cholesky();
+        let n = NUM_SEGMENTS;
+        for i in 0..4 {
+            for j in 0..4 {
+                let product: f64 = (0..n).map(|k| l[i * n + k] * l[j * n + k]).sum();
+                assert_close(product, estimator.segment_cov[i * n + j]);
+            }
+        }
+    }
+
+    #[test]
+    fn cholesky_of_non_positive_definite() {
+        let mut estimator = BayesEstimator::new(&SolverParams::default());
+        let n = NUM_SEGMENTS;
+        // [[1, 2], [2, 1]] has a negative eigenvalue
+        estimator.segment_cov[0] = 1.0;
+        estimator.segment_cov[1] = 2.0;
+        estimator.segment_cov[n] = 2.0;
+        estimator.segment_cov[n + 1] = 1.0;
+        let l = estimator.segment_cov_cholesky();
+        assert!(l.iter().all(|v| v.is_finite()));
+        assert_close(l[0], 1.0);
+        assert_close(l[n], 2.0);
+        assert_eq!(l[n + 1], 0.0);
+    }
+}
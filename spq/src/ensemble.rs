@@ -0,0 +1,132 @@
+use crate::algorithms::Graph;
+use crate::models::*;
+use crate::params::{EnsembleCombine, SolverParams};
+use crate::solver::{CostEstimator, GraphEstimator};
+
+/// Independently annealed `GraphEstimator`s with their own seeds and optional
+/// parameter variants. Costs are the median or mean of the members, and the
+/// spread between members is the cost variance.
+pub struct EnsembleEstimator {
+    members: Vec<GraphEstimator>,
+    combine: EnsembleCombine,
+}
+
+impl EnsembleEstimator {
+    fn member_costs(&self, edge: EdgeIndex) -> Vec<u32> {
+        self.members.iter().map(|m| m.get_cost(edge)).collect()
+    }
+}
+
+// Cost answered for the costs of the members. An even number of costs has the
+// rounded mean of the middle two as its median.
+fn combine_costs(combine: EnsembleCombine, mut costs: Vec<u32>) -> u32 {
+    match combine {
+        EnsembleCombine::Median => {
+            costs.sort_unstable();
+            let n = costs.len();
+            if n % 2 == 1 {
+                costs[n / 2]
+            } else {
+                (costs[n / 2 - 1] + costs[n / 2] + 1) / 2
+            }
+        }
+        EnsembleCombine::Mean => {
+            let sum: u32 = costs.iter().sum();
+            (sum as f64 / costs.len() as f64).round() as u32
+        }
+    }
+}
+
+impl Graph<u32> for EnsembleEstimator {
+    fn get_cost(&self, edge: EdgeIndex) -> u32 {
+        combine_costs(self.combine, self.member_costs(edge))
+    }
+}
+
+impl CostEstimator for EnsembleEstimator {
+    fn new(params: &SolverParams) -> EnsembleEstimator {
+        assert!(params.ensemble_size >= 1, "ensemble_size must be positive");
+        let members = (0..params.ensemble_size)
+            .map(|i| {
+                let mut member_params = SolverParams {
+                    seed: params.seed.wrapping_add(i as u64),
+                    ..params.clone()
+                };
+                if !params.ensemble_variants.is_empty() {
+                    let variant = &params.ensemble_variants[i % params.ensemble_variants.len()];
+                    member_params
+                        .apply_variant(variant)
+                        .unwrap_or_else(|e| panic!("invalid ensemble variant: {}", e));
+                }
+                GraphEstimator::new(&member_params)
+            })
+            .collect();
+        EnsembleEstimator {
+            members,
+            combine: params.ensemble_combine,
+        }
+    }
+
+    fn insert_new_record(&mut self, query: &Query, path: &[Dir], response: u32) {
+        // members share the time of a single estimator
+        let time_share = 1.0 / self.members.len() as f64;
+        for member in &mut self.members {
+            member.add_record(query, path, response);
+            member.update_estimation(1.0, time_share);
+        }
+    }
+
+    /// Mean loss of the members
+    fn loss(&self) -> f64 {
+        self.members.iter().map(|m| m.loss()).sum::<f64>() / self.members.len() as f64
+    }
+
+    fn cost_variance(&self, edge: EdgeIndex) -> Option<f64> {
+        let costs = self.member_costs(edge);
+        let n = costs.len() as f64;
+        let mean = costs.iter().map(|&c| c as f64).sum::<f64>() / n;
+        Some(
+            costs
+                .iter()
+                .map(|&c| (c as f64 - mean).powi(2))
+                .sum::<f64>()
+                / n,
+        )
+    }
+
+    fn validate_cache(&self) {
+        for member in &self.members {
+            member.validate_cache();
+        }
+    }
+
+    fn log_summary(&self) {
+        debug!(
+            "members={} losses={:?}",
+            self.members.len(),
+            self.members.iter().map(|m| m.loss()).collect::<Vec<_>>()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn median() {
+        let median = |costs: &[u32]| combine_costs(EnsembleCombine::Median, costs.to_vec());
+        assert_eq!(median(&[5000]), 5000);
+        assert_eq!(median(&[3000, 9000, 4000]), 4000);
+        assert_eq!(median(&[4000, 1000, 9000, 5000, 2000]), 4000);
+        assert_eq!(median(&[3000, 4000]), 3500);
+        assert_eq!(median(&[4001, 1000, 9000, 4000]), 4001);
+    }
+
+    #[test]
+    fn mean() {
+        let mean = |costs: &[u32]| combine_costs(EnsembleCombine::Mean, costs.to_vec());
+        assert_eq!(mean(&[3000, 9000, 4000]), 5333);
+        assert_eq!(mean(&[1000, 1001]), 1001);
+    }
+}
@@ -0,0 +1,274 @@
+use crate::algorithms::Graph;
+use crate::models::*;
+use crate::params::{Budget, SolverParams};
+use crate::scheduler::TimeScheduler;
+use crate::solver::CostEstimator;
+use std::time::Instant;
+
+/// Fits `cost(edge) = base[line] + deviation[edge]` to the responses by
+/// ridge-regularized least squares.
+///
+/// Each line has a single base, unlike the two parts per line of the
+/// generator and of `GraphEstimator`; the jump between the parts of a split
+/// line is left to the edge deviations. Fitting the breakpoint would make the
+/// problem nonlinear, so this is only a fast linear baseline.
+///
+/// The normal equations `(A^T A + R) x = A^T y + R x0` are solved by conjugate
+/// gradient over the sparse visit matrix `A`, warm-started from the previous
+/// turn, for as long as `SolverParams::budget` allows. `R` pulls line bases
+/// weakly towards the middle of the cost range and edge deviations strongly
+/// towards zero.
+pub struct LeastSquaresEstimator {
+    // dense edge indices visited by each record
+    visited: Vec<Vec<u16>>,
+    responses: Vec<f64>,
+    // line bases in 0..NUM_LINES, followed by edge deviations
+    x: Vec<f64>,
+    x0: Vec<f64>,
+    ridge: Vec<f64>,
+    // A^T y
+    aty: Vec<f64>,
+    budget: Budget,
+    scheduler: TimeScheduler,
+    loss: f64,
+}
+
+const NUM_PARAMS: usize = NUM_LINES + NUM_EDGES;
+
+fn line_of(edge: u16) -> usize {
+    edge as usize / (GRID_LEN - 1)
+}
+
+impl Graph<u32> for LeastSquaresEstimator {
+    fn get_cost(&self, edge: EdgeIndex) -> u32 {
+        let cost = self.x[edge.line.as_usize()] + self.x[NUM_LINES + edge.as_usize()];
+        cost.round().max(1.0) as u32
+    }
+}
+
+impl LeastSquaresEstimator {
+    fn predict(&self, visited: &[u16], x: &[f64]) -> f64 {
+        visited
+            .iter()
+            .map(|&e| x[line_of(e)] + x[NUM_LINES + e as usize])
+            .sum()
+    }
+
+    // (A^T A + R) p
+    fn normal_product(&self, p: &[f64]) -> Vec<f64> {
+        let mut q: Vec<f64> = self.ridge.iter().zip(p).map(|(r, p)| r * p).collect();
+        for visited in &self.visited {
+            let s = self.predict(visited, p);
+            for &e in visited {
+                q[line_of(e)] += s;
+                q[NUM_LINES + e as usize] += s;
+            }
+        }
+        q
+    }
+
+    fn solve(&mut self) {
+        let start = Instant::now();
+        let time_limit = self.scheduler.turn_budget(self.visited.len() - 1);
+        let max_iterations = match self.budget {
+            Budget::Time => None,
+            Budget::IterationsPerTurn(n) => Some(n.max(1)),
+            Budget::TotalIterations(n) => Some((n / NUM_TURN as u64).max(1)),
+        };
+
+        let mx = self.normal_product(&self.x);
+        let mut r: Vec<f64> = (0..NUM_PARAMS)
+            .map(|i| self.aty[i] + self.ridge[i] * self.x0[i] - mx[i])
+            .collect();
+        let mut p = r.clone();
+        let mut rr: f64 = r.iter().map(|v| v * v).sum();
+        let tolerance = rr * 1e-12;
+
+        let mut iterations = 0u64;
+        loop {
+            let done = match max_iterations {
+                Some(n) => iterations >= n,
+                None => start.elapsed() >= time_limit,
+            };
+            if done || rr <= tolerance || rr == 0.0 {
+                break;
+            }
+            iterations += 1;
+            let mp = self.normal_product(&p);
+            let alpha = rr / p.iter().zip(&mp).map(|(a, b)| a * b).sum::<f64>();
+            for i in 0..NUM_PARAMS {
+                self.x[i] += alpha * p[i];
+                r[i] -= alpha * mp[i];
+            }
+            let next_rr: f64 = r.iter().map(|v| v * v).sum();
+            let beta = next_rr / rr;
+            for i in 0..NUM_PARAMS {
+                p[i] = r[i] + beta * p[i];
+            }
+            rr = next_rr;
+        }
+
+        self.loss = self
+            .visited
+            .iter()
+            .zip(&self.responses)
+            .map(|(visited, &response)| (self.predict(visited, &self.x) - response).powi(2))
+            .sum();
+    }
+}
+
+impl CostEstimator for LeastSquaresEstimator {
+    fn new(params: &SolverParams) -> Self {
+        let base = (params.line_cost_lb + params.line_cost_ub) as f64 / 2.0;
+        let mut x0 = vec![0.0; NUM_PARAMS];
+        let mut ridge = vec![params.ridge_edge; NUM_PARAMS];
+        for i in 0..NUM_LINES {
+            x0[i] = base;
+            ridge[i] = params.ridge_line;
+        }
+        LeastSquaresEstimator {
+            visited: Vec::new(),
+            responses: Vec::new(),
+            x: x0.clone(),
+            x0,
+            ridge,
+            aty: vec![0.0; NUM_PARAMS],
+            budget: params.budget,
+            scheduler: TimeScheduler::new(params),
+            loss: 0.0,
+        }
+    }
+
+    fn insert_new_record(&mut self, query: &Query, path: &[Dir], response: u32) {
+        let mut visited = Vec::with_capacity(path.len());
+        let mut cur = query.src;
+        for &dir in path {
+            visited.push(EdgeIndex::from_move(cur, dir).as_usize() as u16);
+            cur = cur.move_to(dir).unwrap();
+        }
+        assert!(cur == query.dest);
+
+        for &e in &visited {
+            self.aty[line_of(e)] += response as f64;
+            self.aty[NUM_LINES + e as usize] += response as f64;
+        }
+        self.visited.push(visited);
+        self.responses.push(response as f64);
+
+        let start_loss = self.loss;
+        self.solve();
+        if self.budget == Budget::Time {
+            self.scheduler.record(start_loss, self.loss);
+        }
+        trace!(
+            "Finish updating estimation. loss={:.0}->{:.0}",
+            start_loss,
+            self.loss
+        );
+    }
+
+    fn loss(&self) -> f64 {
+        self.loss
+    }
+
+    fn log_summary(&self) {
+        debug!("line_bases={:?}", &self.x[..NUM_LINES]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-6 * b.abs().max(1.0), "{} != {}", a, b);
+    }
+
+    fn estimator(budget: Budget) -> LeastSquaresEstimator {
+        LeastSquaresEstimator::new(&SolverParams {
+            budget,
+            ..SolverParams::default()
+        })
+    }
+
+    // A single record of one edge: minimize
+    // (b + d - y)^2 + ridge_line (b - b0)^2 + ridge_edge d^2
+    #[test]
+    fn solve_one_edge() {
+        let params = SolverParams::default();
+        let (rl, re) = (params.ridge_line, params.ridge_edge);
+        let mut estimator = estimator(Budget::IterationsPerTurn(100));
+        let b0 = estimator.x0[0];
+        let query = Query {
+            src: Pos::new(2, 3),
+            dest: Pos::new(2, 4),
+        };
+        let y = 8000.0;
+        estimator.insert_new_record(&query, &[Dir::Right], y as u32);
+
+        let edge = EdgeIndex::from_move(query.src, Dir::Right);
+        let d = (y - b0) / (1.0 + re + re / rl);
+        let b = b0 + re * d / rl;
+        assert_close(estimator.x[edge.line.as_usize()], b);
+        assert_close(estimator.x[NUM_LINES + edge.as_usize()], d);
+        assert_close(estimator.loss(), (b + d - y).powi(2));
+        // other edges of the line share the base
+        let other = EdgeIndex::new(edge.line, 10);
+        assert_eq!(estimator.get_cost(other), b.round() as u32);
+    }
+
+    #[test]
+    fn solve_normal_equations() {
+        let mut estimator = estimator(Budget::IterationsPerTurn(1000));
+        let queries = [
+            (Pos::new(0, 0), Pos::new(3, 5), 21000),
+            (Pos::new(1, 2), Pos::new(4, 1), 9000),
+            (Pos::new(5, 5), Pos::new(0, 2), 30000),
+            (Pos::new(3, 0), Pos::new(3, 6), 25000),
+        ];
+        for &(src, dest, response) in &queries {
+            let mut path = vec![
+                if src.r < dest.r { Dir::Down } else { Dir::Up };
+                (src.r as i32 - dest.r as i32).abs() as usize
+            ];
+            path.extend(vec![
+                if src.c < dest.c {
+                    Dir::Right
+                } else {
+                    Dir::Left
+                };
+                (src.c as i32 - dest.c as i32).abs() as usize
+            ]);
+            estimator.insert_new_record(&Query { src, dest }, &path, response);
+        }
+        let mx = estimator.normal_product(&estimator.x);
+        for (i, product) in mx.iter().enumerate() {
+            let rhs = estimator.aty[i] + estimator.ridge[i] * estimator.x0[i];
+            assert!(
+                (product - rhs).abs() < 1e-6 * rhs.abs().max(1.0),
+                "residual at {}",
+                i
+            );
+        }
+    }
+
+    // One iteration is a single steepest descent step from x0: with r = y - b0
+    // on both unknowns, alpha = r.r / r.Mr = 2 / (ridge_line + ridge_edge + 4)
+    #[test]
+    fn single_iteration() {
+        let params = SolverParams::default();
+        let mut estimator = estimator(Budget::IterationsPerTurn(1));
+        let b0 = estimator.x0[0];
+        let query = Query {
+            src: Pos::new(2, 3),
+            dest: Pos::new(3, 3),
+        };
+        let y = 8000.0;
+        estimator.insert_new_record(&query, &[Dir::Down], y as u32);
+
+        let edge = EdgeIndex::from_move(query.src, Dir::Down);
+        let step = 2.0 * (y - b0) / (params.ridge_line + params.ridge_edge + 4.0);
+        assert_close(estimator.x[edge.line.as_usize()], b0 + step);
+        assert_close(estimator.x[NUM_LINES + edge.as_usize()], step);
+    }
+}
@@ -8,7 +8,19 @@ extern crate log;
 #[macro_use]
 pub mod log;
 
+pub mod bayes;
+pub mod ensemble;
+pub mod least_squares;
+pub mod loss;
 pub mod models;
+#[cfg(feature = "parallel")]
+pub mod multichain;
+pub mod oracle;
+pub mod params;
+pub mod policy;
+pub mod priors;
 pub mod remote;
+pub mod scheduler;
 pub mod simulator;
+pub mod snapshot;
 pub mod solver;
@@ -0,0 +1,178 @@
+use std::str::FromStr;
+
+// Relative errors are scaled so that they are comparable to absolute errors
+// of a typical response (~100000), which keeps the annealing temperatures valid.
+const RELATIVE_SCALE: f64 = 100_000.0;
+
+/// How the mismatch between an estimated path length and its response is measured
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorMetric {
+    /// `estimate - response`
+    Absolute,
+    /// `(estimate - response) / response`
+    Relative,
+    /// `ln(estimate / response)`
+    LogRatio,
+}
+
+impl FromStr for ErrorMetric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "absolute" => Ok(ErrorMetric::Absolute),
+            "relative" => Ok(ErrorMetric::Relative),
+            "log_ratio" => Ok(ErrorMetric::LogRatio),
+            _ => Err("expected absolute, relative or log_ratio".to_string()),
+        }
+    }
+}
+
+impl ErrorMetric {
+    /// Integer error of `estimate` against `response`.
+    ///
+    /// Responses are `length * e` with `e` uniform in [0.9, 1.1], so the
+    /// relative metrics stop long paths from dominating the fit.
+    pub fn error(&self, estimate: i64, response: i64) -> i64 {
+        match self {
+            ErrorMetric::Absolute => estimate - response,
+            ErrorMetric::Relative => ((estimate - response) as f64 / response.max(1) as f64
+                * RELATIVE_SCALE)
+                .round() as i64,
+            ErrorMetric::LogRatio => ((estimate.max(1) as f64 / response.max(1) as f64).ln()
+                * RELATIVE_SCALE)
+                .round() as i64,
+        }
+    }
+}
+
+// Loss of a record whose response is impossible under the uniform noise model.
+// Large enough to never be accepted by annealing, but finite so that the
+// distance to the feasible range still guides the search.
+const INFEASIBLE_LOSS: i64 = 1 << 32;
+
+/// Loss of a single record, summed over all records by the estimator
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Loss {
+    /// `|error|`
+    L1,
+    /// `error^2`
+    L2,
+    /// `error^2` up to `|error| = delta`, linear beyond
+    Huber(i64),
+    /// Exact likelihood of the uniform noise `e` in [0.9, 1.1]: zero when the
+    /// response is within ±10% of the estimate, infinite otherwise.
+    /// Ignores the error metric.
+    Uniform,
+}
+
+impl FromStr for Loss {
+    type Err = String;
+
+    /// `l1`, `l2`, `huber:<delta>` or `uniform`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut it = s.splitn(2, ':');
+        let kind = it.next().unwrap();
+        let delta = it
+            .next()
+            .map(|n| n.parse::<i64>().map_err(|e| e.to_string()));
+        match (kind, delta) {
+            ("l1", None) => Ok(Loss::L1),
+            ("l2", None) => Ok(Loss::L2),
+            ("huber", Some(delta)) => match delta? {
+                delta if delta <= 0 => Err("huber delta must be positive".to_string()),
+                delta => Ok(Loss::Huber(delta)),
+            },
+            ("uniform", None) => Ok(Loss::Uniform),
+            _ => Err("expected l1, l2, huber:<delta> or uniform".to_string()),
+        }
+    }
+}
+
+impl Loss {
+    pub fn eval(&self, metric: ErrorMetric, estimate: i64, response: i64) -> i64 {
+        match *self {
+            Loss::L1 => metric.error(estimate, response).abs(),
+            Loss::L2 => metric.error(estimate, response).pow(2),
+            Loss::Huber(delta) => {
+                let error = metric.error(estimate, response).abs();
+                if error <= delta {
+                    error * error
+                } else {
+                    2 * delta * error - delta * delta
+                }
+            }
+            Loss::Uniform => {
+                // response = estimate * e, 0.9 <= e <= 1.1, with 0.5 of rounding (x10)
+                let lower = 9 * estimate - 5;
+                let upper = 11 * estimate + 5;
+                let scaled = 10 * response;
+                if scaled < lower {
+                    INFEASIBLE_LOSS + (lower - scaled) / 10
+                } else if scaled > upper {
+                    INFEASIBLE_LOSS + (scaled - upper) / 10
+                } else {
+                    0
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_metrics() {
+        assert_eq!(ErrorMetric::Absolute.error(1100, 1000), 100);
+        assert_eq!(ErrorMetric::Absolute.error(900, 1000), -100);
+        assert_eq!(ErrorMetric::Relative.error(1100, 1000), 10_000);
+        assert_eq!(ErrorMetric::Relative.error(1000, 1000), 0);
+        assert_eq!(ErrorMetric::LogRatio.error(1000, 1000), 0);
+        assert_eq!(
+            ErrorMetric::LogRatio.error(2000, 1000),
+            (2f64.ln() * RELATIVE_SCALE).round() as i64
+        );
+        assert_eq!(
+            ErrorMetric::LogRatio.error(1000, 2000),
+            -ErrorMetric::LogRatio.error(2000, 1000)
+        );
+    }
+
+    #[test]
+    fn losses() {
+        let metric = ErrorMetric::Absolute;
+        assert_eq!(Loss::L1.eval(metric, 1000, 1030), 30);
+        assert_eq!(Loss::L2.eval(metric, 1000, 1030), 900);
+        assert_eq!(Loss::Huber(50).eval(metric, 1000, 1030), 900);
+        assert_eq!(Loss::Huber(50).eval(metric, 1000, 1050), 2500);
+        // linear beyond delta, continuous at delta
+        assert_eq!(Loss::Huber(50).eval(metric, 1000, 1100), 7500);
+        assert_eq!(Loss::Huber(50).eval(metric, 1000, 900), 7500);
+    }
+
+    #[test]
+    fn uniform_loss() {
+        let metric = ErrorMetric::Absolute;
+        for &response in &[900, 1000, 1100] {
+            assert_eq!(Loss::Uniform.eval(metric, 1000, response), 0);
+        }
+        let below = Loss::Uniform.eval(metric, 1000, 800);
+        let far_below = Loss::Uniform.eval(metric, 1000, 700);
+        assert!(below >= INFEASIBLE_LOSS);
+        assert!(far_below > below);
+        assert!(Loss::Uniform.eval(metric, 1000, 1200) >= INFEASIBLE_LOSS);
+    }
+
+    #[test]
+    fn parse_loss() {
+        assert_eq!("l1".parse(), Ok(Loss::L1));
+        assert_eq!("l2".parse(), Ok(Loss::L2));
+        assert_eq!("huber:300".parse(), Ok(Loss::Huber(300)));
+        assert_eq!("uniform".parse(), Ok(Loss::Uniform));
+        for s in &["huber", "huber:0", "huber:-5", "l2:1", "l3"] {
+            assert!(s.parse::<Loss>().is_err(), "{} should be rejected", s);
+        }
+    }
+}
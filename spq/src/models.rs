@@ -5,10 +5,42 @@ use std::ops::{Index, IndexMut};
 
 pub const NUM_TURN: usize = 1000;
 pub const GRID_LEN: usize = 30;
+pub const NUM_LINES: usize = 2 * GRID_LEN;
+pub const NUM_EDGES: usize = NUM_LINES * (GRID_LEN - 1);
 
 pub trait Environment {
     fn next_query(&self) -> Option<Query>;
     fn do_answer(&mut self, path: &[Dir]) -> u32;
+
+    /// Ground truth, if this environment wants the estimate measured against it.
+    /// Measuring costs time after every turn, so only environments recording
+    /// the errors should return one.
+    fn oracle(&self) -> Option<&dyn Oracle> {
+        None
+    }
+
+    /// Receives the error of the estimate after each turn when `oracle` is available
+    fn record_estimation_error(&mut self, _error: EstimationError) {}
+}
+
+/// Ground truth of a test case, known only to local environments
+pub trait Oracle {
+    fn true_cost(&self, edge: EdgeIndex) -> u32;
+
+    /// Parts of `line` as (first x, base cost)
+    fn true_line_parts(&self, line: LineIndex) -> Vec<(u8, u16)>;
+}
+
+/// Root mean squared errors of an estimate against the truth
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EstimationError {
+    /// Over the costs of all edges
+    pub edge_rmse: f64,
+    /// Over the base costs at every edge. `None` if the estimator has no line parts.
+    pub line_base_rmse: Option<f64>,
+    /// Over the true breakpoints, as the distance to the nearest estimated one.
+    /// A line estimated as a single part counts its ends as breakpoints.
+    pub breakpoint_rmse: Option<f64>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -31,6 +63,15 @@ impl Dir {
             Dir::Right => Dir::Left,
         }
     }
+    pub fn from_char(c: char) -> Option<Dir> {
+        match c {
+            'U' => Some(Dir::Up),
+            'D' => Some(Dir::Down),
+            'L' => Some(Dir::Left),
+            'R' => Some(Dir::Right),
+            _ => None,
+        }
+    }
     pub fn to_char(&self) -> char {
         match self {
             Dir::Up => 'U',
@@ -159,6 +200,19 @@ impl LineIndex {
         assert!((index as usize) < GRID_LEN);
         LineIndex { axis, index }
     }
+    /// Dense index in `0..NUM_LINES`
+    pub fn as_usize(&self) -> usize {
+        self.axis.as_usize() * GRID_LEN + self.index as usize
+    }
+    /// Inverse of `as_usize`
+    pub fn from_usize(i: usize) -> Self {
+        let axis = if i < GRID_LEN {
+            Axis::Horizontal
+        } else {
+            Axis::Vertical
+        };
+        LineIndex::new(axis, (i % GRID_LEN) as u8)
+    }
     pub fn from_move(p: Pos, d: Dir) -> Self {
         assert!(p.move_to(d).is_some(), "{:?} moving {:?}", p, d);
         match d {
@@ -186,6 +240,24 @@ impl EdgeIndex {
         EdgeIndex { line, x }
     }
 
+    pub fn iter() -> impl Iterator<Item = EdgeIndex> {
+        LineIndex::iter()
+            .flat_map(|line| (0..GRID_LEN as u8 - 1).map(move |x| EdgeIndex { line, x }))
+    }
+
+    /// Dense index in `0..NUM_EDGES`
+    pub fn as_usize(&self) -> usize {
+        self.line.as_usize() * (GRID_LEN - 1) + self.x as usize
+    }
+
+    /// Inverse of `as_usize`
+    pub fn from_usize(i: usize) -> Self {
+        EdgeIndex::new(
+            LineIndex::from_usize(i / (GRID_LEN - 1)),
+            (i % (GRID_LEN - 1)) as u8,
+        )
+    }
+
     pub fn from_move(p: Pos, d: Dir) -> Self {
         assert!(p.move_to(d).is_some(), "{:?} moving {:?}", p, d);
         let line = LineIndex::from_move(p, d);
@@ -205,6 +277,55 @@ impl EdgeIndex {
     }
 }
 
+const EDGE_SET_WORDS: usize = (NUM_EDGES + 63) / 64;
+
+/// Set of edges stored as a bitset over `EdgeIndex::as_usize`
+#[derive(Debug, Clone, PartialEq)]
+pub struct EdgeSet([u64; EDGE_SET_WORDS]);
+
+impl EdgeSet {
+    pub fn new() -> EdgeSet {
+        EdgeSet([0; EDGE_SET_WORDS])
+    }
+
+    /// Returns whether `edge` was newly inserted
+    pub fn insert(&mut self, edge: EdgeIndex) -> bool {
+        let i = edge.as_usize();
+        let bit = 1 << (i % 64);
+        let inserted = self.0[i / 64] & bit == 0;
+        self.0[i / 64] |= bit;
+        inserted
+    }
+
+    pub fn contains(&self, edge: EdgeIndex) -> bool {
+        let i = edge.as_usize();
+        self.0[i / 64] >> (i % 64) & 1 == 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|&w| w == 0)
+    }
+
+    /// Edges in the order of `EdgeIndex::as_usize`
+    pub fn iter(&self) -> impl Iterator<Item = EdgeIndex> + '_ {
+        self.0.iter().enumerate().flat_map(|(w, &bits)| {
+            std::iter::successors(Some(bits), |&b| Some(b & b.wrapping_sub(1)))
+                .take_while(|&b| b != 0)
+                .map(move |b| EdgeIndex::from_usize(w * 64 + b.trailing_zeros() as usize))
+        })
+    }
+}
+
+impl Default for EdgeSet {
+    fn default() -> EdgeSet {
+        EdgeSet::new()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Query {
     pub src: Pos,
@@ -296,13 +417,54 @@ impl<T: Copy> Graph<T> for GridGraph<T> {
     }
 }
 
-#[derive(PartialOrd, PartialEq)]
+#[derive(PartialEq)]
 pub struct UnwrapOrd<T: PartialOrd + PartialEq>(pub T);
 
 impl<T: PartialOrd + PartialEq> Eq for UnwrapOrd<T> {}
 
+impl<T: PartialOrd + PartialEq> PartialOrd for UnwrapOrd<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl<T: PartialOrd + PartialEq> Ord for UnwrapOrd<T> {
     fn cmp(&self, other: &Self) -> Ordering {
         self.0.partial_cmp(&other.0).unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edge_set() {
+        let mut set = EdgeSet::new();
+        assert!(set.is_empty());
+        // bits on both sides of the first word boundary and the last edge
+        let indices = [0, 63, 64, 65, 127, 128, NUM_EDGES - 1];
+        for &i in indices.iter().rev() {
+            assert!(set.insert(EdgeIndex::from_usize(i)));
+        }
+        assert!(!set.insert(EdgeIndex::from_usize(64)));
+        assert!(!set.insert(EdgeIndex::from_usize(63)));
+        assert_eq!(set.len(), indices.len());
+        assert!(!set.is_empty());
+        assert!(set.contains(EdgeIndex::from_usize(63)));
+        assert!(!set.contains(EdgeIndex::from_usize(62)));
+        assert!(!set.contains(EdgeIndex::from_usize(66)));
+        let iterated: Vec<usize> = set.iter().map(|e| e.as_usize()).collect();
+        assert_eq!(iterated, indices);
+    }
+
+    #[test]
+    fn edge_set_of_all_edges() {
+        let mut set = EdgeSet::new();
+        for edge in EdgeIndex::iter() {
+            assert!(set.insert(edge));
+        }
+        assert_eq!(set.len(), NUM_EDGES);
+        assert!(set.iter().eq(EdgeIndex::iter()));
+    }
+}
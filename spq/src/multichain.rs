@@ -0,0 +1,215 @@
+//! Several `GraphEstimator` chains annealed on their own threads.
+//!
+//! Only built with the `parallel` feature for local experiments; the
+//! submission stays single-threaded.
+use crate::algorithms::Graph;
+use crate::models::*;
+use crate::params::{ChainMode, SolverParams};
+use crate::solver::{CostEstimator, GraphEstimator};
+use rand::prelude::*;
+use rand::rngs::SmallRng;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+// Thread annealing the chains sent to it, alive as long as the estimator
+struct Worker {
+    jobs: Option<Sender<(GraphEstimator, f64)>>,
+    results: Receiver<GraphEstimator>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    fn spawn() -> Worker {
+        let (jobs, job_receiver) = channel::<(GraphEstimator, f64)>();
+        let (result_sender, results) = channel();
+        let handle = thread::spawn(move || {
+            for (mut chain, temp_scale) in job_receiver {
+                chain.update_estimation(temp_scale, 1.0);
+                if result_sender.send(chain).is_err() {
+                    break;
+                }
+            }
+        });
+        Worker {
+            jobs: Some(jobs),
+            results,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        // closing the job channel ends the thread
+        self.jobs.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+pub struct MultiChainEstimator {
+    chains: Vec<GraphEstimator>,
+    // one per chain
+    workers: Vec<Worker>,
+    // temperature multiplier of each chain
+    temp_scales: Vec<f64>,
+    mode: ChainMode,
+    average: bool,
+    end_temp: f64,
+    // chain with the lowest loss
+    best: usize,
+    swaps: usize,
+    rng: SmallRng,
+}
+
+impl MultiChainEstimator {
+    fn anneal_chains(&mut self) {
+        for ((chain, &temp_scale), worker) in self
+            .chains
+            .drain(..)
+            .zip(&self.temp_scales)
+            .zip(&self.workers)
+        {
+            worker
+                .jobs
+                .as_ref()
+                .unwrap()
+                .send((chain, temp_scale))
+                .expect("annealing worker stopped");
+        }
+        self.chains = self
+            .workers
+            .iter()
+            .map(|worker| worker.results.recv().expect("annealing chain panicked"))
+            .collect();
+    }
+
+    fn find_best(&self) -> usize {
+        (0..self.chains.len())
+            .min_by(|&a, &b| {
+                self.chains[a]
+                    .loss()
+                    .partial_cmp(&self.chains[b].loss())
+                    .unwrap()
+            })
+            .unwrap()
+    }
+
+    // Swaps the temperatures of chains next to each other on the ladder
+    fn exchange_temperatures(&mut self) {
+        let mut ladder: Vec<usize> = (0..self.chains.len()).collect();
+        ladder.sort_by(|&a, &b| {
+            self.temp_scales[a]
+                .partial_cmp(&self.temp_scales[b])
+                .unwrap()
+        });
+        for w in ladder.windows(2) {
+            let (cold, hot) = (w[0], w[1]);
+            let beta_diff = 1.0 / (self.end_temp * self.temp_scales[cold])
+                - 1.0 / (self.end_temp * self.temp_scales[hot]);
+            let energy_diff = self.chains[cold].loss() - self.chains[hot].loss();
+            if self.rng.gen::<f64>() < (energy_diff * beta_diff).exp() {
+                self.temp_scales.swap(cold, hot);
+                self.swaps += 1;
+            }
+        }
+    }
+}
+
+impl Graph<u32> for MultiChainEstimator {
+    fn get_cost(&self, edge: EdgeIndex) -> u32 {
+        if self.average {
+            let sum: u32 = self.chains.iter().map(|c| c.get_cost(edge)).sum();
+            (sum as f64 / self.chains.len() as f64).round() as u32
+        } else {
+            self.chains[self.best].get_cost(edge)
+        }
+    }
+}
+
+impl CostEstimator for MultiChainEstimator {
+    fn new(params: &SolverParams) -> MultiChainEstimator {
+        assert!(params.num_chains >= 1, "num_chains must be positive");
+        let chains = (0..params.num_chains)
+            .map(|i| {
+                let params = SolverParams {
+                    seed: params.seed.wrapping_add(i as u64),
+                    ..params.clone()
+                };
+                GraphEstimator::new(&params)
+            })
+            .collect();
+        let temp_scales = (0..params.num_chains)
+            .map(|i| match params.chain_mode {
+                ChainMode::Tempering => params.tempering_ratio.powi(i as i32),
+                _ => 1.0,
+            })
+            .collect();
+        MultiChainEstimator {
+            chains,
+            workers: (0..params.num_chains).map(|_| Worker::spawn()).collect(),
+            temp_scales,
+            mode: params.chain_mode,
+            average: params.chain_average,
+            end_temp: params.end_temp,
+            best: 0,
+            swaps: 0,
+            rng: SmallRng::seed_from_u64(params.seed ^ 0xc4a1_c4a1),
+        }
+    }
+
+    fn insert_new_record(&mut self, query: &Query, path: &[Dir], response: u32) {
+        for chain in &mut self.chains {
+            chain.add_record(query, path, response);
+        }
+        self.anneal_chains();
+        self.best = self.find_best();
+        match self.mode {
+            ChainMode::Independent => {}
+            ChainMode::Restart => {
+                let best = self.chains[self.best].clone();
+                for (i, chain) in self.chains.iter_mut().enumerate() {
+                    if i != self.best {
+                        chain.restart_from(&best);
+                    }
+                }
+            }
+            ChainMode::Tempering => self.exchange_temperatures(),
+        }
+        trace!(
+            "Chain losses={:?} best={}",
+            self.chains.iter().map(|c| c.loss()).collect::<Vec<_>>(),
+            self.best
+        );
+    }
+
+    fn loss(&self) -> f64 {
+        self.chains[self.best].loss()
+    }
+
+    fn line_parts(&self, line: LineIndex) -> Option<Vec<(u8, u32)>> {
+        if self.average {
+            None
+        } else {
+            self.chains[self.best].line_parts(line)
+        }
+    }
+
+    fn validate_cache(&self) {
+        for chain in &self.chains {
+            chain.validate_cache();
+        }
+    }
+
+    fn log_summary(&self) {
+        debug!(
+            "chains={} best={} swaps={} losses={:?}",
+            self.chains.len(),
+            self.best,
+            self.swaps,
+            self.chains.iter().map(|c| c.loss()).collect::<Vec<_>>()
+        );
+        self.chains[self.best].log_summary();
+    }
+}
@@ -0,0 +1,173 @@
+//! Measures estimates against the ground truth given by an `Oracle`.
+use crate::models::*;
+use crate::solver::CostEstimator;
+
+fn rmse(square_sum: f64, count: usize) -> f64 {
+    if count == 0 {
+        0.0
+    } else {
+        (square_sum / count as f64).sqrt()
+    }
+}
+
+// Base cost at `x` of a line given as (first x, base cost) parts
+fn base_at<T: Copy>(parts: &[(u8, T)], x: u8) -> T {
+    parts
+        .iter()
+        .rev()
+        .find(|&&(start, _)| start <= x)
+        .unwrap()
+        .1
+}
+
+impl EstimationError {
+    /// Errors of the current estimate of `estimator`
+    pub fn measure<C: CostEstimator + ?Sized>(oracle: &dyn Oracle, estimator: &C) -> Self {
+        let edge_square_sum: f64 = EdgeIndex::iter()
+            .map(|e| (estimator.get_cost(e) as f64 - oracle.true_cost(e) as f64).powi(2))
+            .sum();
+
+        let mut base_square_sum = 0.0;
+        let mut breakpoint_square_sum = 0.0;
+        let mut breakpoints = 0;
+        let mut has_parts = true;
+        for line in LineIndex::iter() {
+            let parts = match estimator.line_parts(line) {
+                Some(parts) => parts,
+                None => {
+                    has_parts = false;
+                    break;
+                }
+            };
+            let true_parts = oracle.true_line_parts(line);
+            for x in 0..GRID_LEN as u8 - 1 {
+                let diff = base_at(&parts, x) as f64 - base_at(&true_parts, x) as f64;
+                base_square_sum += diff.powi(2);
+            }
+            let mut estimated: Vec<u8> = parts[1..].iter().map(|p| p.0).collect();
+            if estimated.is_empty() {
+                estimated = vec![0, GRID_LEN as u8 - 1];
+            }
+            for &(true_start, _) in &true_parts[1..] {
+                let distance = estimated
+                    .iter()
+                    .map(|&x| (x as i32 - true_start as i32).abs())
+                    .min()
+                    .unwrap();
+                breakpoint_square_sum += (distance as f64).powi(2);
+                breakpoints += 1;
+            }
+        }
+
+        EstimationError {
+            edge_rmse: rmse(edge_square_sum, NUM_EDGES),
+            line_base_rmse: if has_parts {
+                Some(rmse(base_square_sum, NUM_EDGES))
+            } else {
+                None
+            },
+            breakpoint_rmse: if has_parts && breakpoints > 0 {
+                Some(rmse(breakpoint_square_sum, breakpoints))
+            } else {
+                None
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::Graph;
+    use crate::params::SolverParams;
+
+    // Every line costs 1000 below x = 10 and 3000 from there, except the first
+    // edge, which deviates by 300
+    struct TrueGraph;
+
+    const SPECIAL_EDGE: usize = 0;
+
+    impl Oracle for TrueGraph {
+        fn true_cost(&self, edge: EdgeIndex) -> u32 {
+            let base = base_at(&self.true_line_parts(edge.line), edge.x) as u32;
+            if edge.as_usize() == SPECIAL_EDGE {
+                base + 300
+            } else {
+                base
+            }
+        }
+
+        fn true_line_parts(&self, _line: LineIndex) -> Vec<(u8, u16)> {
+            vec![(0, 1000), (10, 3000)]
+        }
+    }
+
+    // Edge costs are the line bases, if any; a flat 2000 otherwise
+    struct Estimate(Option<Vec<(u8, u32)>>);
+
+    impl Graph<u32> for Estimate {
+        fn get_cost(&self, edge: EdgeIndex) -> u32 {
+            match &self.0 {
+                Some(parts) => base_at(parts, edge.x),
+                None => 2000,
+            }
+        }
+    }
+
+    impl CostEstimator for Estimate {
+        fn new(_params: &SolverParams) -> Self {
+            Estimate(None)
+        }
+
+        fn insert_new_record(&mut self, _query: &Query, _path: &[Dir], _response: u32) {}
+
+        fn loss(&self) -> f64 {
+            0.0
+        }
+
+        fn line_parts(&self, _line: LineIndex) -> Option<Vec<(u8, u32)>> {
+            self.0.clone()
+        }
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9 * b.max(1.0), "{} != {}", a, b);
+    }
+
+    #[test]
+    fn measure_single_part() {
+        let error = EstimationError::measure(&TrueGraph, &Estimate(Some(vec![(0, 2000)])));
+        let n = NUM_EDGES as f64;
+        assert_close(
+            error.edge_rmse,
+            (((n - 1.0) * 1e6 + 700.0 * 700.0) / n).sqrt(),
+        );
+        assert_close(error.line_base_rmse.unwrap(), 1000.0);
+        // the ends 0 and 29 stand in for the breakpoints; 10 is nearest to 0
+        assert_close(error.breakpoint_rmse.unwrap(), 10.0);
+    }
+
+    #[test]
+    fn measure_shifted_breakpoint() {
+        let estimate = Estimate(Some(vec![(0, 1000), (12, 3000)]));
+        let error = EstimationError::measure(&TrueGraph, &estimate);
+        // x = 10 and 11 of every line are off by 2000, plus the special edge by 300
+        let lines = NUM_LINES as f64;
+        let n = NUM_EDGES as f64;
+        let edge_square_sum = 2.0 * lines * 2000.0 * 2000.0 + 300.0 * 300.0;
+        assert_close(error.edge_rmse, (edge_square_sum / n).sqrt());
+        assert_close(
+            error.line_base_rmse.unwrap(),
+            (2.0 * lines * 4e6 / n).sqrt(),
+        );
+        assert_close(error.breakpoint_rmse.unwrap(), 2.0);
+    }
+
+    #[test]
+    fn measure_without_parts() {
+        let error = EstimationError::measure(&TrueGraph, &Estimate(None));
+        assert!(error.edge_rmse > 0.0);
+        assert_eq!(error.line_base_rmse, None);
+        assert_eq!(error.breakpoint_rmse, None);
+    }
+}
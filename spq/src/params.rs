@@ -0,0 +1,602 @@
+use crate::loss::{ErrorMetric, Loss};
+use crate::models::NUM_TURN;
+use crate::policy::PathPolicy;
+use crate::scheduler::Schedule;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Upper bound of `SolverParams::max_parts`
+pub const MAX_PARTS: usize = 4;
+
+/// How long the estimator anneals after each turn
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Budget {
+    /// `time_limit * time_ratio` of wall-clock time, spread over the turns by a `TimeScheduler`
+    Time,
+    /// A fixed number of iterations per turn
+    IterationsPerTurn(u64),
+    /// A fixed number of iterations in total, split evenly over the turns
+    TotalIterations(u64),
+}
+
+impl FromStr for Budget {
+    type Err = String;
+
+    /// `time`, `iters:<per turn>` or `total:<whole run>`. Every turn must get
+    /// at least one iteration.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut it = s.splitn(2, ':');
+        let kind = it.next().unwrap();
+        let count = it
+            .next()
+            .map(|n| n.parse::<u64>().map_err(|e| e.to_string()));
+        match (kind, count) {
+            ("time", None) => Ok(Budget::Time),
+            ("iters", Some(n)) => match n? {
+                0 => Err("iters must be positive".to_string()),
+                n => Ok(Budget::IterationsPerTurn(n)),
+            },
+            ("total", Some(n)) => match n? {
+                n if n < NUM_TURN as u64 => Err(format!(
+                    "total must be at least {} (one per turn)",
+                    NUM_TURN
+                )),
+                n => Ok(Budget::TotalIterations(n)),
+            },
+            _ => Err("expected time, iters:<n> or total:<n>".to_string()),
+        }
+    }
+}
+
+/// How `GraphEstimator` decides whether a line needs more than one part
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SplitSelection {
+    /// Parts are added and removed freely by annealing
+    Free,
+    /// Each extra part costs a BIC-style penalty, and lines whose split does
+    /// not pay for itself are tied back to a single part
+    Line,
+    /// Same penalty, but all lines are tied at once when the instance as a
+    /// whole looks single-segment
+    Instance,
+}
+
+impl FromStr for SplitSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "free" => Ok(SplitSelection::Free),
+            "line" => Ok(SplitSelection::Line),
+            "instance" => Ok(SplitSelection::Instance),
+            _ => Err("expected free, line or instance".to_string()),
+        }
+    }
+}
+
+/// How the step of line and edge cost moves is chosen in annealing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepMode {
+    /// Always `step`
+    Fixed,
+    /// `step` scaled by the square root of the relative temperature
+    Temperature,
+    /// Separate steps for line and edge moves, adapted towards
+    /// `step_target_acceptance`
+    Acceptance,
+}
+
+impl FromStr for StepMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fixed" => Ok(StepMode::Fixed),
+            "temperature" => Ok(StepMode::Temperature),
+            "acceptance" => Ok(StepMode::Acceptance),
+            _ => Err("expected fixed, temperature or acceptance".to_string()),
+        }
+    }
+}
+
+/// How the chains of the multi-chain estimator interact after each turn
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChainMode {
+    /// Chains never interact
+    Independent,
+    /// Every chain restarts from the chain with the lowest loss
+    Restart,
+    /// Chains anneal at temperatures `tempering_ratio^i` times the schedule and
+    /// neighbouring temperatures swap by the Metropolis criterion
+    Tempering,
+}
+
+impl FromStr for ChainMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "independent" => Ok(ChainMode::Independent),
+            "restart" => Ok(ChainMode::Restart),
+            "tempering" => Ok(ChainMode::Tempering),
+            _ => Err("expected independent, restart or tempering".to_string()),
+        }
+    }
+}
+
+/// How the ensemble estimator combines the costs of its members
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EnsembleCombine {
+    Median,
+    Mean,
+}
+
+impl FromStr for EnsembleCombine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "median" => Ok(EnsembleCombine::Median),
+            "mean" => Ok(EnsembleCombine::Mean),
+            _ => Err("expected median or mean".to_string()),
+        }
+    }
+}
+
+/// Tunable parameters of the solver.
+///
+/// `Default` holds the values used by the submission.
+#[derive(Debug, Clone)]
+pub struct SolverParams {
+    pub time_limit: Duration,
+    /// Seed of the random number generator owned by the estimator
+    pub seed: u64,
+    /// Fraction of `time_limit` spent on estimation
+    pub time_ratio: f64,
+    /// How the estimation time is spread over the turns
+    pub schedule: Schedule,
+    /// Extra weight of the first turn over the last one with `Schedule::Adaptive`
+    pub schedule_early_weight: f64,
+    /// Annealing is stopped by wall-clock time unless an iteration budget is given.
+    /// With a fixed `seed`, iteration budgets make runs reproducible.
+    pub budget: Budget,
+    /// Reseed the estimator's generator from `seed` and the turn before each
+    /// turn, so that runs resumed from a snapshot continue exactly
+    pub reseed_per_turn: bool,
+
+    /// How paths are chosen from the estimate
+    pub policy: PathPolicy,
+    /// Initial weight of the standard deviation with `PathPolicy::Optimistic`
+    pub lcb_k: f64,
+    /// Number of opening turns that prefer paths covering unvisited half lines.
+    /// The opening ends early once `opening_coverage` of them have been visited.
+    pub opening_turns: usize,
+    pub opening_coverage: f64,
+    /// Opening paths may be this much longer than the shortest one on the estimate
+    pub opening_slack: f64,
+
+    /// Error of each record in the annealing loss
+    pub error_metric: ErrorMetric,
+    /// Loss of each record in annealing
+    pub loss: Loss,
+
+    pub line_cost_lb: i64,
+    pub line_cost_ub: i64,
+    pub edge_cost_lb: i64,
+    pub edge_cost_ub: i64,
+    /// Replace the edge cost bounds by `±D * d_margin`, where the deviation
+    /// amplitude `D` is estimated from the edges visited at least `d_min_visits` times
+    pub adaptive_d: bool,
+    pub d_min_visits: usize,
+    pub d_margin: f64,
+    /// Weight of the penalty on squared differences between the deviations of
+    /// neighbouring edges on a line. 0 disables it.
+    pub smooth_weight: f64,
+
+    /// Initialize line costs and breakpoints from the priors of generated
+    /// instances instead of `line_cost_lb` and even splits (graph)
+    pub prior_init: bool,
+    /// Range of the number of constant-cost parts per line (graph)
+    pub min_parts: usize,
+    pub max_parts: usize,
+    /// Model selection between split and unsplit lines. Lines are only tied
+    /// when `min_parts` is 1.
+    pub split_selection: SplitSelection,
+    /// Multiplier of the BIC penalty `2 ln(n) * loss / n` per extra part. Only
+    /// applied with the L2 loss.
+    pub split_penalty: f64,
+
+    pub step: i64,
+    pub step_mode: StepMode,
+    pub step_target_acceptance: f64,
+    /// Probability that a line cost move jumps by up to `line_jump` (at least 1)
+    /// instead of a step
+    pub line_jump_prob: f64,
+    pub line_jump: i64,
+    /// Probability that a cost move targets an edge of a record with a large error
+    pub residual_target_prob: f64,
+    /// Probability that a move changes coupled parameters together: a breakpoint
+    /// with both part costs, a part cost against its edge deviations, or two
+    /// part costs visited by the same record
+    pub compound_prob: f64,
+    pub start_temp: f64,
+    pub end_temp: f64,
+
+    /// Number of annealing chains run in parallel (multichain)
+    pub num_chains: usize,
+    pub chain_mode: ChainMode,
+    pub tempering_ratio: f64,
+    /// Answer with the mean cost of all chains instead of the best chain (multichain)
+    pub chain_average: bool,
+
+    /// Number of graph estimators in the ensemble, each with its own seed (ensemble)
+    pub ensemble_size: usize,
+    pub ensemble_combine: EnsembleCombine,
+    /// Parameter overrides of the members, member `i` taking `i % len`.
+    /// Written as `key=value,key=value;key=value` with `;` between members.
+    pub ensemble_variants: Vec<String>,
+
+    /// Ridge weight pulling line bases to the middle of the cost range (least squares)
+    pub ridge_line: f64,
+    /// Ridge weight pulling edge deviations to zero (least squares)
+    pub ridge_edge: f64,
+
+    /// Prior standard deviation of line costs (bayes)
+    pub bayes_line_sd: f64,
+    /// Prior correlation between the two halves of a line (bayes)
+    pub bayes_part_corr: f64,
+    /// Prior standard deviation of edge deviations (bayes)
+    pub bayes_edge_sd: f64,
+}
+
+impl Default for SolverParams {
+    fn default() -> Self {
+        SolverParams {
+            time_limit: Duration::from_secs(2),
+            seed: 0,
+            time_ratio: 0.9,
+            schedule: Schedule::Uniform,
+            schedule_early_weight: 1.0,
+            budget: Budget::Time,
+            reseed_per_turn: false,
+            policy: PathPolicy::Greedy,
+            lcb_k: 1.0,
+            opening_turns: 0,
+            opening_coverage: 1.0,
+            opening_slack: 0.1,
+            error_metric: ErrorMetric::Absolute,
+            loss: Loss::L2,
+            line_cost_lb: 1000,
+            line_cost_ub: 9000,
+            edge_cost_lb: -400,
+            edge_cost_ub: 400,
+            adaptive_d: false,
+            d_min_visits: 3,
+            d_margin: 1.2,
+            smooth_weight: 0.0,
+            prior_init: false,
+            min_parts: 2,
+            max_parts: 2,
+            split_selection: SplitSelection::Free,
+            split_penalty: 1.0,
+            step: 50,
+            step_mode: StepMode::Fixed,
+            step_target_acceptance: 0.3,
+            line_jump_prob: 0.0,
+            line_jump: 1000,
+            residual_target_prob: 0.0,
+            compound_prob: 0.0,
+            start_temp: 10000.0,
+            end_temp: 1.0,
+            num_chains: 4,
+            chain_mode: ChainMode::Independent,
+            tempering_ratio: 2.0,
+            chain_average: false,
+            ensemble_size: 3,
+            ensemble_combine: EnsembleCombine::Median,
+            ensemble_variants: Vec::new(),
+            ridge_line: 25.0,
+            ridge_edge: 400.0,
+            bayes_line_sd: 2300.0,
+            bayes_part_corr: 0.5,
+            bayes_edge_sd: 700.0,
+        }
+    }
+}
+
+fn parse_value<T: FromStr>(key: &str, value: &str) -> Result<T, String>
+where
+    T::Err: std::fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|e| format!("invalid value for {}: {} ({})", key, value, e))
+}
+
+fn parse_in_range<T>(key: &str, value: &str, range: RangeInclusive<T>) -> Result<T, String>
+where
+    T: FromStr + PartialOrd + std::fmt::Display,
+    T::Err: std::fmt::Display,
+{
+    let parsed = parse_value(key, value)?;
+    if range.contains(&parsed) {
+        Ok(parsed)
+    } else {
+        Err(format!(
+            "{} must be between {} and {}: {}",
+            key,
+            range.start(),
+            range.end(),
+            value
+        ))
+    }
+}
+
+impl SolverParams {
+    /// Overrides a single parameter by name
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "time_limit" => self.time_limit = Duration::from_millis(parse_value(key, value)?),
+            "seed" => self.seed = parse_value(key, value)?,
+            "time_ratio" => self.time_ratio = parse_in_range(key, value, 0.0..=1.0)?,
+            "schedule" => self.schedule = parse_value(key, value)?,
+            "schedule_early_weight" => self.schedule_early_weight = parse_value(key, value)?,
+            "budget" => self.budget = parse_value(key, value)?,
+            "reseed_per_turn" => self.reseed_per_turn = parse_value(key, value)?,
+            "policy" => self.policy = parse_value(key, value)?,
+            "lcb_k" => self.lcb_k = parse_value(key, value)?,
+            "opening_turns" => self.opening_turns = parse_value(key, value)?,
+            "opening_coverage" => self.opening_coverage = parse_value(key, value)?,
+            "opening_slack" => self.opening_slack = parse_value(key, value)?,
+            "error_metric" => self.error_metric = parse_value(key, value)?,
+            "loss" => self.loss = parse_value(key, value)?,
+            "line_cost_lb" => self.line_cost_lb = parse_in_range(key, value, 1..=std::i64::MAX)?,
+            "line_cost_ub" => self.line_cost_ub = parse_value(key, value)?,
+            "edge_cost_lb" => self.edge_cost_lb = parse_value(key, value)?,
+            "edge_cost_ub" => self.edge_cost_ub = parse_value(key, value)?,
+            "adaptive_d" => self.adaptive_d = parse_value(key, value)?,
+            "d_min_visits" => self.d_min_visits = parse_value(key, value)?,
+            "d_margin" => self.d_margin = parse_value(key, value)?,
+            "smooth_weight" => self.smooth_weight = parse_value(key, value)?,
+            "prior_init" => self.prior_init = parse_value(key, value)?,
+            "min_parts" => self.min_parts = parse_in_range(key, value, 1..=MAX_PARTS)?,
+            "max_parts" => self.max_parts = parse_in_range(key, value, 1..=MAX_PARTS)?,
+            "split_selection" => self.split_selection = parse_value(key, value)?,
+            "split_penalty" => self.split_penalty = parse_value(key, value)?,
+            "step" => self.step = parse_value(key, value)?,
+            "step_mode" => self.step_mode = parse_value(key, value)?,
+            "step_target_acceptance" => self.step_target_acceptance = parse_value(key, value)?,
+            "line_jump_prob" => self.line_jump_prob = parse_value(key, value)?,
+            "line_jump" => self.line_jump = parse_in_range(key, value, 1..=std::i64::MAX)?,
+            "residual_target_prob" => self.residual_target_prob = parse_value(key, value)?,
+            "compound_prob" => self.compound_prob = parse_value(key, value)?,
+            "start_temp" => self.start_temp = parse_value(key, value)?,
+            "end_temp" => self.end_temp = parse_value(key, value)?,
+            "num_chains" => self.num_chains = parse_in_range(key, value, 1..=std::usize::MAX)?,
+            "chain_mode" => self.chain_mode = parse_value(key, value)?,
+            "tempering_ratio" => self.tempering_ratio = parse_value(key, value)?,
+            "chain_average" => self.chain_average = parse_value(key, value)?,
+            "ensemble_size" => {
+                self.ensemble_size = parse_in_range(key, value, 1..=std::usize::MAX)?
+            }
+            "ensemble_combine" => self.ensemble_combine = parse_value(key, value)?,
+            "ensemble_variants" => {
+                let variants: Vec<String> =
+                    value.split(';').map(|v| v.trim().to_string()).collect();
+                for variant in &variants {
+                    self.clone().apply_variant(variant)?;
+                }
+                self.ensemble_variants = variants;
+            }
+            "ridge_line" => self.ridge_line = parse_value(key, value)?,
+            "ridge_edge" => self.ridge_edge = parse_value(key, value)?,
+            "bayes_line_sd" => self.bayes_line_sd = parse_value(key, value)?,
+            "bayes_part_corr" => self.bayes_part_corr = parse_value(key, value)?,
+            "bayes_edge_sd" => self.bayes_edge_sd = parse_value(key, value)?,
+            _ => return Err(format!("unknown parameter: {}", key)),
+        }
+        Ok(())
+    }
+
+    /// Checks the constraints between parameters, which `set` cannot check
+    /// since they may be overridden in any order
+    pub fn validate(&self) -> Result<(), String> {
+        if self.line_cost_lb > self.line_cost_ub || self.edge_cost_lb > self.edge_cost_ub {
+            return Err("cost bounds are empty".to_string());
+        }
+        if self.line_cost_lb + self.edge_cost_lb < 1 {
+            return Err("line_cost_lb + edge_cost_lb must be positive".to_string());
+        }
+        if self.min_parts > self.max_parts {
+            return Err(format!(
+                "min_parts {} is above max_parts {}",
+                self.min_parts, self.max_parts
+            ));
+        }
+        Ok(())
+    }
+
+    /// Applies comma-separated `key=value` overrides
+    pub fn apply_variant(&mut self, variant: &str) -> Result<(), String> {
+        for pair in variant.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            self.set_pair(pair)?;
+        }
+        self.validate()
+    }
+
+    /// Applies an override in `key=value` form
+    pub fn set_pair(&mut self, pair: &str) -> Result<(), String> {
+        let mut it = pair.splitn(2, '=');
+        let key = it.next().unwrap().trim();
+        let value = it
+            .next()
+            .ok_or_else(|| format!("expected key=value: {}", pair))?
+            .trim();
+        self.set(key, value)
+    }
+
+    /// Applies overrides from `key = value` lines. Empty lines and `#` comments are ignored.
+    pub fn update_from_str(&mut self, text: &str) -> Result<(), String> {
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            self.set_pair(line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_from_str() {
+        let mut params = SolverParams::default();
+        params
+            .update_from_str(
+                "# tuned\n\nstart_temp = 5000.5\nstep=20  # smaller steps\nend_temp = 0.5\n",
+            )
+            .unwrap();
+        assert_eq!(params.start_temp, 5000.5);
+        assert_eq!(params.step, 20);
+        assert_eq!(params.end_temp, 0.5);
+        assert_eq!(params.time_limit, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn reject_invalid_params() {
+        let mut params = SolverParams::default();
+        assert!(params.set_pair("step").is_err());
+        assert!(params.set_pair("unknown=1").is_err());
+        assert!(params.set_pair("step=fast").is_err());
+        assert!(params
+            .update_from_str("step = 10\nend_temp = cold")
+            .is_err());
+        for &(key, value) in &[
+            ("time_ratio", "-0.5"),
+            ("time_ratio", "1.5"),
+            ("time_ratio", "NaN"),
+            ("line_cost_lb", "0"),
+            ("line_cost_lb", "-1000"),
+        ] {
+            assert!(params.set(key, value).is_err(), "{}={}", key, value);
+        }
+        assert_eq!(params.time_ratio, 0.9);
+        assert_eq!(params.line_cost_lb, 1000);
+    }
+
+    #[test]
+    fn validate_cost_bounds() {
+        assert!(SolverParams::default().validate().is_ok());
+        let mut params = SolverParams::default();
+        params.set("line_cost_ub", "500").unwrap();
+        assert!(params.validate().is_err());
+        let mut params = SolverParams::default();
+        params.set("edge_cost_lb", "-1000").unwrap();
+        assert!(params.validate().is_err());
+    }
+
+    #[test]
+    fn parse_budget() {
+        assert_eq!("time".parse(), Ok(Budget::Time));
+        assert_eq!("iters:300".parse(), Ok(Budget::IterationsPerTurn(300)));
+        assert_eq!("total:5000".parse(), Ok(Budget::TotalIterations(5000)));
+        assert_eq!("total:1000".parse(), Ok(Budget::TotalIterations(1000)));
+    }
+
+    #[test]
+    fn reject_invalid_budget() {
+        for s in &[
+            "iters:0",
+            "total:0",
+            "total:999",
+            "iters",
+            "iters:",
+            "iters:-1",
+            "time:1",
+            "fast",
+        ] {
+            assert!(s.parse::<Budget>().is_err(), "{} should be rejected", s);
+        }
+        let mut params = SolverParams::default();
+        assert!(params.set("budget", "iters:0").is_err());
+        assert_eq!(params.budget, Budget::Time);
+    }
+
+    #[test]
+    fn reject_invalid_parts() {
+        let mut params = SolverParams::default();
+        for &(key, value) in &[
+            ("min_parts", "0"),
+            ("max_parts", "0"),
+            ("max_parts", "5"),
+            ("min_parts", "-1"),
+        ] {
+            assert!(params.set(key, value).is_err(), "{}={}", key, value);
+        }
+        assert_eq!((params.min_parts, params.max_parts), (2, 2));
+
+        params.set("min_parts", "3").unwrap();
+        assert!(params.validate().is_err());
+        params.set("max_parts", "4").unwrap();
+        assert!(params.validate().is_ok());
+        params.set("min_parts", "1").unwrap();
+        params.set("max_parts", "1").unwrap();
+        assert!(params.validate().is_ok());
+    }
+
+    #[test]
+    fn reject_non_positive_line_jump() {
+        let mut params = SolverParams::default();
+        assert!(params.set("line_jump", "0").is_err());
+        assert!(params.set("line_jump", "-5").is_err());
+        assert_eq!(params.line_jump, 1000);
+        params.set("line_jump", "1").unwrap();
+        assert_eq!(params.line_jump, 1);
+    }
+
+    #[test]
+    fn reject_no_chains() {
+        let mut params = SolverParams::default();
+        assert!(params.set("num_chains", "0").is_err());
+        assert_eq!(params.num_chains, 4);
+        params.set("num_chains", "1").unwrap();
+        assert_eq!(params.num_chains, 1);
+    }
+
+    #[test]
+    fn apply_variant() {
+        let mut params = SolverParams::default();
+        params.apply_variant("step=20, seed=3,").unwrap();
+        assert_eq!((params.step, params.seed), (20, 3));
+        assert!(params.apply_variant("step=30,seed").is_err());
+        assert!(params.clone().apply_variant("min_parts=3").is_err());
+        params.apply_variant("min_parts=3,max_parts=4").unwrap();
+
+        params.set("ensemble_variants", "step=10;loss=l1").unwrap();
+        assert_eq!(params.ensemble_variants, vec!["step=10", "loss=l1"]);
+        for variants in &[
+            "step=10;loss=l3",
+            "max_parts=9",
+            "step=10;min_parts=4,max_parts=2",
+        ] {
+            assert!(
+                params.set("ensemble_variants", variants).is_err(),
+                "{}",
+                variants
+            );
+        }
+        assert_eq!(params.ensemble_variants, vec!["step=10", "loss=l1"]);
+    }
+
+    #[test]
+    fn reject_empty_ensemble() {
+        let mut params = SolverParams::default();
+        assert!(params.set("ensemble_size", "0").is_err());
+        assert_eq!(params.ensemble_size, 3);
+    }
+}
@@ -0,0 +1,307 @@
+use crate::algorithms::{compute_path_cost, compute_shortest_path, Graph};
+use crate::models::*;
+use crate::params::SolverParams;
+use crate::solver::CostEstimator;
+use rand::prelude::*;
+use rand::rngs::SmallRng;
+use std::str::FromStr;
+
+/// How a path is chosen from the estimator each turn
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathPolicy {
+    /// Shortest path on the point estimate
+    Greedy,
+    /// Shortest path on a cost graph sampled from the estimator's uncertainty.
+    /// Falls back to `Greedy` for estimators without uncertainty.
+    Thompson,
+    /// Shortest path on the optimistic costs `mean - k * sd`, where `k` decays
+    /// linearly from `lcb_k` to zero over the turns.
+    /// Same as `Greedy` for estimators without uncertainty.
+    Optimistic,
+}
+
+impl FromStr for PathPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "greedy" => Ok(PathPolicy::Greedy),
+            "thompson" => Ok(PathPolicy::Thompson),
+            "lcb" => Ok(PathPolicy::Optimistic),
+            _ => Err("expected greedy, thompson or lcb".to_string()),
+        }
+    }
+}
+
+/// Sample from the standard normal distribution (Box-Muller)
+pub fn standard_normal<R: Rng>(rng: &mut R) -> f64 {
+    let u1 = 1.0 - rng.gen::<f64>();
+    let u2 = rng.gen::<f64>();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+/// Lower confidence bound of the estimated costs
+pub struct OptimisticGraph<'a, C: CostEstimator> {
+    estimator: &'a C,
+    k: f64,
+}
+
+impl<'a, C: CostEstimator> OptimisticGraph<'a, C> {
+    pub fn new(estimator: &'a C, k: f64) -> Self {
+        OptimisticGraph { estimator, k }
+    }
+}
+
+impl<'a, C: CostEstimator> Graph<u32> for OptimisticGraph<'a, C> {
+    fn get_cost(&self, edge: EdgeIndex) -> u32 {
+        let mean = self.estimator.get_cost(edge) as f64;
+        let sd = self
+            .estimator
+            .cost_variance(edge)
+            .unwrap_or(0.0)
+            .max(0.0)
+            .sqrt();
+        (mean - self.k * sd).round().max(1.0) as u32
+    }
+}
+
+// Each line is split into halves when tracking coverage in the opening
+const COVERAGE_PARTS: usize = 2;
+
+fn coverage_segment(edge: EdgeIndex) -> usize {
+    edge.line.as_usize() * COVERAGE_PARTS + edge.x as usize * COVERAGE_PARTS / (GRID_LEN - 1)
+}
+
+fn straight(path: &mut Vec<Dir>, dir: Dir, count: u8) {
+    path.extend((0..count).map(|_| dir));
+}
+
+/// Monotone paths with at most two turns: along the source row, down a
+/// column and along the destination row, or the same with rows and columns
+/// swapped. Each covers long stretches of few lines.
+fn staircase_paths(query: &Query) -> Vec<Vec<Dir>> {
+    let (src, dest) = (query.src, query.dest);
+    let h = if src.c <= dest.c {
+        Dir::Right
+    } else {
+        Dir::Left
+    };
+    let v = if src.r <= dest.r { Dir::Down } else { Dir::Up };
+    let mut paths = Vec::new();
+    for turn_at in 0..=query.width() {
+        let mut path = Vec::new();
+        straight(&mut path, h, turn_at);
+        straight(&mut path, v, query.height());
+        straight(&mut path, h, query.width() - turn_at);
+        paths.push(path);
+    }
+    for turn_at in 0..=query.height() {
+        let mut path = Vec::new();
+        straight(&mut path, v, turn_at);
+        straight(&mut path, h, query.width());
+        straight(&mut path, v, query.height() - turn_at);
+        paths.push(path);
+    }
+    paths
+}
+
+pub struct PathSelector {
+    policy: PathPolicy,
+    lcb_k: f64,
+    opening_turns: usize,
+    opening_coverage: f64,
+    opening_slack: f64,
+    // visits per half line
+    coverage: Vec<u32>,
+    turn: usize,
+    rng: SmallRng,
+}
+
+impl PathSelector {
+    pub fn new(params: &SolverParams) -> PathSelector {
+        PathSelector {
+            policy: params.policy,
+            lcb_k: params.lcb_k,
+            opening_turns: params.opening_turns,
+            opening_coverage: params.opening_coverage,
+            opening_slack: params.opening_slack,
+            coverage: vec![0; NUM_LINES * COVERAGE_PARTS],
+            turn: 0,
+            // keep a stream separate from the estimator's
+            rng: SmallRng::seed_from_u64(params.seed ^ 0x5eed_5eed),
+        }
+    }
+
+    /// Fraction of half lines visited at least once
+    pub fn coverage(&self) -> f64 {
+        self.coverage.iter().filter(|&&c| c > 0).count() as f64 / self.coverage.len() as f64
+    }
+
+    /// Continues from `turn` as if the earlier turns had been selected here.
+    /// Coverage of those turns is not restored.
+    pub fn set_turn(&mut self, turn: usize) {
+        self.turn = turn;
+    }
+
+    fn in_opening(&self, turn: usize) -> bool {
+        turn < self.opening_turns && self.coverage() < self.opening_coverage
+    }
+
+    fn record_coverage(&mut self, src: Pos, path: &[Dir]) {
+        let mut p = src;
+        for &dir in path {
+            self.coverage[coverage_segment(EdgeIndex::from_move(p, dir))] += 1;
+            p = p.move_to(dir).unwrap();
+        }
+    }
+
+    /// Among the shortest path and the staircase paths whose estimated length is
+    /// within `opening_slack` of the shortest, picks the one visiting the most
+    /// unvisited half lines.
+    fn select_opening<C: CostEstimator>(&self, estimator: &C, query: &Query) -> (Vec<Dir>, u32) {
+        let shortest = compute_shortest_path(estimator, query.src, query.dest);
+        let limit = shortest.1 as f64 * (1.0 + self.opening_slack);
+        let mut best = shortest;
+        let mut best_gain = self.coverage_gain(query.src, &best.0);
+        for path in staircase_paths(query) {
+            let length = compute_path_cost(estimator, query.src, &path);
+            if length as f64 > limit {
+                continue;
+            }
+            let gain = self.coverage_gain(query.src, &path);
+            if gain > best_gain || (gain == best_gain && length < best.1) {
+                best = (path, length);
+                best_gain = gain;
+            }
+        }
+        best
+    }
+
+    // Number of unvisited half lines on `path`
+    fn coverage_gain(&self, src: Pos, path: &[Dir]) -> usize {
+        let mut segments = Vec::new();
+        let mut p = src;
+        for &dir in path {
+            let segment = coverage_segment(EdgeIndex::from_move(p, dir));
+            if self.coverage[segment] == 0 && !segments.contains(&segment) {
+                segments.push(segment);
+            }
+            p = p.move_to(dir).unwrap();
+        }
+        segments.len()
+    }
+
+    /// Path for `query` and its length on the point estimate
+    pub fn select<C: CostEstimator>(&mut self, estimator: &C, query: &Query) -> (Vec<Dir>, u32) {
+        let turn = self.turn;
+        self.turn += 1;
+        let (path, length) = if self.in_opening(turn) {
+            trace!("Opening turn. coverage={:.3}", self.coverage());
+            self.select_opening(estimator, query)
+        } else {
+            self.select_by_policy(estimator, query, turn)
+        };
+        self.record_coverage(query.src, &path);
+        (path, length)
+    }
+
+    fn select_by_policy<C: CostEstimator>(
+        &mut self,
+        estimator: &C,
+        query: &Query,
+        turn: usize,
+    ) -> (Vec<Dir>, u32) {
+        match self.policy {
+            PathPolicy::Greedy => compute_shortest_path(estimator, query.src, query.dest),
+            PathPolicy::Thompson => match estimator.sample_costs(&mut self.rng) {
+                Some(sample) => {
+                    let (path, _) = compute_shortest_path(&sample, query.src, query.dest);
+                    let length = compute_path_cost(estimator, query.src, &path);
+                    (path, length)
+                }
+                None => compute_shortest_path(estimator, query.src, query.dest),
+            },
+            PathPolicy::Optimistic => {
+                let k = self.lcb_k * (1.0 - turn as f64 / NUM_TURN as f64);
+                let graph = OptimisticGraph::new(estimator, k);
+                let (path, _) = compute_shortest_path(&graph, query.src, query.dest);
+                let length = compute_path_cost(estimator, query.src, &path);
+                (path, length)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::Simulator;
+    use crate::solver::GraphEstimator;
+
+    #[test]
+    fn staircase_paths_are_monotone() {
+        let corners = [(3, 4), (20, 7), (12, 12), (0, 29), (29, 0)];
+        for &(sr, sc) in &corners {
+            for &(dr, dc) in &corners {
+                let query = Query {
+                    src: Pos::new(sr, sc),
+                    dest: Pos::new(dr, dc),
+                };
+                let paths = staircase_paths(&query);
+                assert_eq!(paths.len(), (query.width() + query.height()) as usize + 2);
+                for path in paths {
+                    assert_eq!(path.len(), (query.width() + query.height()) as usize);
+                    let mut p = query.src;
+                    for dir in path {
+                        let next = p.move_to(dir).unwrap();
+                        // every move brings the path closer to dest
+                        let distance = |p: Pos| {
+                            (p.r as i32 - dr as i32).abs() + (p.c as i32 - dc as i32).abs()
+                        };
+                        assert_eq!(distance(next), distance(p) - 1);
+                        p = next;
+                    }
+                    assert!(p == query.dest);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn opening_increases_coverage() {
+        let params = SolverParams {
+            opening_turns: NUM_TURN,
+            opening_coverage: 1.0,
+            ..SolverParams::default()
+        };
+        let estimator = GraphEstimator::new(&params);
+        let mut selector = PathSelector::new(&params);
+        let mut simulator = Simulator::from_seed(1);
+        assert_eq!(selector.coverage(), 0.0);
+        let mut coverage = 0.0;
+        for _ in 0..20 {
+            let query = simulator.next_query().unwrap();
+            let (path, _) = selector.select(&estimator, &query);
+            simulator.do_answer(&path);
+            assert!(selector.coverage() > coverage);
+            coverage = selector.coverage();
+        }
+        let visited = selector.coverage.iter().filter(|&&c| c > 0).count();
+        assert_eq!(
+            coverage,
+            visited as f64 / (NUM_LINES * COVERAGE_PARTS) as f64
+        );
+    }
+
+    #[test]
+    fn standard_normal_moments() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        let n = 100_000;
+        let samples: Vec<f64> = (0..n).map(|_| standard_normal(&mut rng)).collect();
+        let mean = samples.iter().sum::<f64>() / n as f64;
+        let var = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n as f64;
+        assert!(mean.abs() < 0.02, "mean {}", mean);
+        assert!((var - 1.0).abs() < 0.02, "variance {}", var);
+        assert!(samples.iter().all(|x| x.is_finite()));
+    }
+}
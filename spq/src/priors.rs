@@ -0,0 +1,15 @@
+//! Priors of generated instances. Generated by `train_priors --num 10000`, do not edit.
+use crate::models::GRID_LEN;
+
+/// Mean base cost of a part. Costs are symmetric over their range, so this is
+/// about its midpoint and carries little information.
+pub const LINE_COST_MEAN: f64 = 5000.504;
+
+/// Distribution of the first x of the second part, by x. Nearly uniform
+/// over 1..GRID_LEN - 1.
+pub const BREAKPOINT_HISTOGRAM: [f64; GRID_LEN - 1] = [
+    0.000000, 0.035390, 0.036209, 0.035908, 0.035427, 0.035488, 0.035701, 0.035897, 0.035657,
+    0.036303, 0.035999, 0.036320, 0.036056, 0.035924, 0.036083, 0.035779, 0.035999, 0.035414,
+    0.035410, 0.035441, 0.035326, 0.035779, 0.035847, 0.035096, 0.035383, 0.035752, 0.035383,
+    0.035583, 0.035444,
+];
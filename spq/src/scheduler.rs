@@ -0,0 +1,171 @@
+use crate::models::NUM_TURN;
+use crate::params::SolverParams;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// How the annealing time is spread over the turns
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Schedule {
+    /// Remaining time split evenly over the remaining turns
+    Uniform,
+    /// More time for early turns and for turns where the loss keeps moving
+    Adaptive,
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uniform" => Ok(Schedule::Uniform),
+            "adaptive" => Ok(Schedule::Adaptive),
+            _ => Err("expected uniform or adaptive".to_string()),
+        }
+    }
+}
+
+// Bounds of the factor applied when recent turns moved the loss more (or less) than usual
+const MIN_ACTIVITY_FACTOR: f64 = 0.5;
+const MAX_ACTIVITY_FACTOR: f64 = 2.0;
+const ACTIVITY_DECAY: f64 = 0.9;
+
+/// Hands out annealing time per turn from a global budget of `time_limit * time_ratio`.
+///
+/// Budgets are always computed from the real remaining time, so time spent
+/// outside annealing and overruns of earlier turns are paid back by later turns.
+#[derive(Clone)]
+pub struct TimeScheduler {
+    start: Instant,
+    total: Duration,
+    schedule: Schedule,
+    early_weight: f64,
+    // exponential moving average of the relative loss improvement per turn
+    recent_improvement: f64,
+    // plain average of the relative loss improvement per turn
+    mean_improvement: f64,
+    recorded_turns: usize,
+}
+
+impl TimeScheduler {
+    pub fn new(params: &SolverParams) -> TimeScheduler {
+        TimeScheduler {
+            start: Instant::now(),
+            total: params.time_limit.mul_f64(params.time_ratio),
+            schedule: params.schedule,
+            early_weight: params.schedule_early_weight,
+            recent_improvement: 0.0,
+            mean_improvement: 0.0,
+            recorded_turns: 0,
+        }
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.total
+            .checked_sub(self.start.elapsed())
+            .unwrap_or_default()
+    }
+
+    // Prior weight of a turn before looking at the loss
+    fn weight(&self, turn: usize) -> f64 {
+        1.0 + self.early_weight * (1.0 - turn as f64 / NUM_TURN as f64)
+    }
+
+    fn activity_factor(&self) -> f64 {
+        if self.recorded_turns == 0 || self.mean_improvement <= 0.0 {
+            return 1.0;
+        }
+        (self.recent_improvement / self.mean_improvement)
+            .sqrt()
+            .max(MIN_ACTIVITY_FACTOR)
+            .min(MAX_ACTIVITY_FACTOR)
+    }
+
+    /// Annealing time for `turn` (0-indexed)
+    pub fn turn_budget(&self, turn: usize) -> Duration {
+        let remaining = self.remaining();
+        let remaining_turns = NUM_TURN.saturating_sub(turn).max(1);
+        match self.schedule {
+            Schedule::Uniform => remaining / remaining_turns as u32,
+            Schedule::Adaptive => {
+                let weight_sum: f64 = (turn..turn + remaining_turns).map(|t| self.weight(t)).sum();
+                let share = self.weight(turn) / weight_sum * self.activity_factor();
+                // never give a single turn more than half of what is left
+                remaining.mul_f64(share.min(0.5))
+            }
+        }
+    }
+
+    /// Reports the loss before and after annealing a turn
+    pub fn record(&mut self, start_loss: f64, end_loss: f64) {
+        let improvement = if start_loss > 0.0 {
+            ((start_loss - end_loss) / start_loss).max(0.0)
+        } else {
+            0.0
+        };
+        self.recorded_turns += 1;
+        self.mean_improvement += (improvement - self.mean_improvement) / self.recorded_turns as f64;
+        self.recent_improvement = if self.recorded_turns == 1 {
+            improvement
+        } else {
+            self.recent_improvement * ACTIVITY_DECAY + improvement * (1.0 - ACTIVITY_DECAY)
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Long enough that the time passing during a test is negligible
+    fn scheduler(schedule: Schedule, early_weight: f64) -> TimeScheduler {
+        TimeScheduler::new(&SolverParams {
+            time_limit: Duration::from_secs(1000),
+            time_ratio: 1.0,
+            schedule,
+            schedule_early_weight: early_weight,
+            ..SolverParams::default()
+        })
+    }
+
+    fn assert_close(a: Duration, secs: f64) {
+        assert!(
+            (a.as_secs_f64() - secs).abs() < 0.01 * secs,
+            "{:?} != {}s",
+            a,
+            secs
+        );
+    }
+
+    #[test]
+    fn uniform_budget() {
+        let scheduler = scheduler(Schedule::Uniform, 3.0);
+        assert_close(scheduler.turn_budget(0), 1000.0 / NUM_TURN as f64);
+        assert_close(scheduler.turn_budget(600), 1000.0 / (NUM_TURN - 600) as f64);
+    }
+
+    #[test]
+    fn early_turns_get_more() {
+        let w = 3.0;
+        let adaptive = scheduler(Schedule::Adaptive, w);
+        let weight = |t: usize| 1.0 + w * (1.0 - t as f64 / NUM_TURN as f64);
+        let weight_sum: f64 = (0..NUM_TURN).map(weight).sum();
+        assert_close(adaptive.turn_budget(0), 1000.0 * weight(0) / weight_sum);
+        let weight_sum: f64 = (500..NUM_TURN).map(weight).sum();
+        assert_close(adaptive.turn_budget(500), 1000.0 * weight(500) / weight_sum);
+        assert!(adaptive.turn_budget(0).as_secs_f64() > 1000.0 / NUM_TURN as f64);
+
+        // without the early weight, adaptive is uniform until losses are recorded
+        let flat = scheduler(Schedule::Adaptive, 0.0);
+        assert_close(flat.turn_budget(200), 1000.0 / (NUM_TURN - 200) as f64);
+    }
+
+    #[test]
+    fn last_turn() {
+        let uniform = scheduler(Schedule::Uniform, 0.0);
+        assert_close(uniform.turn_budget(NUM_TURN - 1), 1000.0);
+        assert_close(uniform.turn_budget(NUM_TURN), 1000.0);
+        // a single adaptive turn never takes more than half of what is left
+        let adaptive = scheduler(Schedule::Adaptive, 3.0);
+        assert_close(adaptive.turn_budget(NUM_TURN - 1), 500.0);
+    }
+}
@@ -49,6 +49,17 @@ impl GraphParams {
             GraphParams::Double(p) => p.d,
         }
     }
+
+    /// Parts of `line` as (first x, base cost)
+    pub fn line_parts(&self, line: LineIndex) -> Vec<(u8, u16)> {
+        let i = line.index as usize;
+        match (self, line.axis) {
+            (GraphParams::Single(p), Axis::Horizontal) => vec![(0, p.h[i])],
+            (GraphParams::Single(p), Axis::Vertical) => vec![(0, p.v[i])],
+            (GraphParams::Double(p), Axis::Horizontal) => vec![(0, p.h[i].0), (p.rm[i], p.h[i].1)],
+            (GraphParams::Double(p), Axis::Vertical) => vec![(0, p.v[i].0), (p.cm[i], p.v[i].1)],
+        }
+    }
 }
 
 pub struct Simulator {
@@ -87,6 +98,16 @@ impl Simulator {
     }
 }
 
+impl Oracle for Simulator {
+    fn true_cost(&self, edge: EdgeIndex) -> u32 {
+        self.graph[edge]
+    }
+
+    fn true_line_parts(&self, line: LineIndex) -> Vec<(u8, u16)> {
+        self.graph_params.line_parts(line)
+    }
+}
+
 impl Environment for Simulator {
     fn next_query(&self) -> Option<Query> {
         if self.turn < NUM_TURN {
@@ -98,7 +119,7 @@ impl Environment for Simulator {
 
     fn do_answer(&mut self, path: &[Dir]) -> u32 {
         let query = self.queries[self.turn].clone();
-        let length = self.compute_path_length(&path).expect("invalid path");
+        let length = self.compute_path_length(path).expect("invalid path");
         let best = compute_shortest_cost(&self.graph, query.query.src, query.query.dest);
         let ratio = best as f64 / length as f64;
         assert!(
@@ -146,18 +167,18 @@ impl Simulator {
             })
             .collect::<Vec<_>>();
         let mut x = vec![vec![]; GRID_LEN];
-        for i in 0..GRID_LEN {
-            x[i].push(0);
+        for xi in x.iter_mut() {
+            xi.push(0);
             if m == 2 {
-                x[i].push(rng.gen_range(1, GRID_LEN as u32 - 1) as usize);
+                xi.push(rng.gen_range(1, GRID_LEN as u32 - 1) as usize);
             }
-            x[i].push(GRID_LEN - 1);
+            xi.push(GRID_LEN - 1);
         }
         let mut h = [[0; GRID_LEN - 1]; GRID_LEN];
         for i in 0..GRID_LEN {
             for p in 0..m {
-                for j in x[i][p]..x[i][p + 1] {
-                    h[i][j] = (hb[i][p] + rng.gen_range(-d, d + 1)) as u32;
+                for hij in &mut h[i][x[i][p]..x[i][p + 1]] {
+                    *hij = (hb[i][p] + rng.gen_range(-d, d + 1)) as u32;
                 }
             }
         }
@@ -170,18 +191,18 @@ impl Simulator {
             })
             .collect::<Vec<_>>();
         let mut y = vec![vec![]; GRID_LEN];
-        for j in 0..GRID_LEN {
-            y[j].push(0);
+        for yj in y.iter_mut() {
+            yj.push(0);
             if m == 2 {
-                y[j].push(rng.gen_range(1, GRID_LEN as u32 - 1) as usize);
+                yj.push(rng.gen_range(1, GRID_LEN as u32 - 1) as usize);
             }
-            y[j].push(GRID_LEN - 1);
+            yj.push(GRID_LEN - 1);
         }
         let mut v = [[0; GRID_LEN]; GRID_LEN - 1];
         for j in 0..GRID_LEN {
             for p in 0..m {
-                for i in y[j][p]..y[j][p + 1] {
-                    v[i][j] = (vb[j][p] + rng.gen_range(-d, d + 1)) as u32;
+                for vi in &mut v[y[j][p]..y[j][p + 1]] {
+                    vi[j] = (vb[j][p] + rng.gen_range(-d, d + 1)) as u32;
                 }
             }
         }
@@ -0,0 +1,339 @@
+//! Plain-text snapshots of the `GraphEstimator` state after some turn.
+//!
+//! ```text
+//! snapshot 1
+//! record <src r> <src c> <dest r> <dest c> <path> <response>   (one per turn)
+//! line <num parts> <start> <cost> ...                          (NUM_LINES lines)
+//! edges <deviation> ...                                        (NUM_EDGES values)
+//! bounds <lb> <ub>
+//! steps <line step> <edge step>
+//! totals <total cost> ...                                      (one per turn)
+//! loss <loss> <smooth loss>
+//! ```
+use crate::models::*;
+use crate::params::MAX_PARTS;
+use std::fmt::Write;
+use std::str::FromStr;
+
+const VERSION: &str = "1";
+
+#[derive(Debug, Clone)]
+pub struct SnapshotRecord {
+    pub query: Query,
+    pub path: Vec<Dir>,
+    pub response: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub records: Vec<SnapshotRecord>,
+    /// (start, cost) of each part, by `LineIndex::as_usize`
+    pub line_parts: Vec<Vec<(u8, u32)>>,
+    /// Deviations by `EdgeIndex::as_usize`
+    pub edge_costs: Vec<i32>,
+    pub edge_cost_bounds: (i64, i64),
+    pub steps: [f64; 2],
+    // cached values checked when the estimator is restored
+    pub total_costs: Vec<u32>,
+    pub loss: i64,
+    pub smooth_loss: i64,
+}
+
+impl Snapshot {
+    /// Number of turns answered before the snapshot
+    pub fn turn(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Checks everything the estimator relies on when it is restored:
+    /// - the sizes of the tables
+    /// - every record is a path inside the grid from its source to its destination
+    /// - every line has between 1 and `MAX_PARTS` parts, the first starting at 0
+    ///   and the others at strictly increasing x in `1..GRID_LEN - 1`
+    /// - every edge costs at least 1
+    pub fn validate(&self) -> Result<(), String> {
+        if self.line_parts.len() != NUM_LINES || self.edge_costs.len() != NUM_EDGES {
+            return Err("incomplete snapshot".to_string());
+        }
+        if self.total_costs.len() != self.records.len() {
+            return Err("number of totals does not match the records".to_string());
+        }
+        for (turn, r) in self.records.iter().enumerate() {
+            let inside = |p: Pos| (p.r as usize) < GRID_LEN && (p.c as usize) < GRID_LEN;
+            if !inside(r.query.src) || !inside(r.query.dest) {
+                return Err(format!("record {} is outside the grid", turn));
+            }
+            let mut cur = r.query.src;
+            for &dir in &r.path {
+                cur = cur
+                    .move_to(dir)
+                    .ok_or_else(|| format!("path of record {} leaves the grid", turn))?;
+            }
+            if cur != r.query.dest {
+                return Err(format!("path of record {} does not reach its dest", turn));
+            }
+        }
+        for (i, parts) in self.line_parts.iter().enumerate() {
+            if parts.is_empty() || parts.len() > MAX_PARTS {
+                return Err(format!("line {} has {} parts", i, parts.len()));
+            }
+            if parts[0].0 != 0 {
+                return Err(format!("first part of line {} does not start at 0", i));
+            }
+            let mut prev = 0;
+            for &(start, _) in &parts[1..] {
+                if start <= prev || start as usize >= GRID_LEN - 1 {
+                    return Err(format!("invalid part start {} of line {}", start, i));
+                }
+                prev = start;
+            }
+        }
+        for (i, &deviation) in self.edge_costs.iter().enumerate() {
+            let edge = EdgeIndex::from_usize(i);
+            let parts = &self.line_parts[edge.line.as_usize()];
+            let base = parts.iter().rev().find(|p| p.0 <= edge.x).unwrap().1;
+            if base as i64 + deviation as i64 <= 0 {
+                return Err(format!("edge {} has a non-positive cost", i));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_text(&self) -> String {
+        fn join<T: ToString>(values: impl Iterator<Item = T>) -> String {
+            values.map(|v| v.to_string()).collect::<Vec<_>>().join(" ")
+        }
+        let mut text = String::new();
+        writeln!(text, "snapshot {}", VERSION).unwrap();
+        for r in &self.records {
+            let path: String = r.path.iter().map(|d| d.to_char()).collect();
+            let (src, dest) = (r.query.src, r.query.dest);
+            writeln!(
+                text,
+                "record {} {} {} {} {} {}",
+                src.r, src.c, dest.r, dest.c, path, r.response
+            )
+            .unwrap();
+        }
+        for parts in &self.line_parts {
+            let values = parts
+                .iter()
+                .flat_map(|&(start, cost)| vec![start as u32, cost]);
+            writeln!(text, "line {} {}", parts.len(), join(values)).unwrap();
+        }
+        writeln!(text, "edges {}", join(self.edge_costs.iter())).unwrap();
+        let (lb, ub) = self.edge_cost_bounds;
+        writeln!(text, "bounds {} {}", lb, ub).unwrap();
+        writeln!(text, "steps {} {}", self.steps[0], self.steps[1]).unwrap();
+        writeln!(text, "totals {}", join(self.total_costs.iter())).unwrap();
+        writeln!(text, "loss {} {}", self.loss, self.smooth_loss).unwrap();
+        text
+    }
+}
+
+fn parse<T: FromStr>(token: Option<&str>, line_no: usize) -> Result<T, String> {
+    token
+        .ok_or_else(|| format!("line {}: missing value", line_no))?
+        .parse()
+        .map_err(|_| format!("line {}: invalid value", line_no))
+}
+
+fn parse_all<T: FromStr>(tokens: &[&str], line_no: usize) -> Result<Vec<T>, String> {
+    tokens.iter().map(|&t| parse(Some(t), line_no)).collect()
+}
+
+fn parse_path(token: Option<&str>, line_no: usize) -> Result<Vec<Dir>, String> {
+    token
+        .ok_or_else(|| format!("line {}: missing path", line_no))?
+        .chars()
+        .map(|c| Dir::from_char(c).ok_or_else(|| format!("line {}: invalid path", line_no)))
+        .collect()
+}
+
+impl FromStr for Snapshot {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut snapshot = Snapshot {
+            records: Vec::new(),
+            line_parts: Vec::new(),
+            edge_costs: Vec::new(),
+            edge_cost_bounds: (0, 0),
+            steps: [0.0; 2],
+            total_costs: Vec::new(),
+            loss: 0,
+            smooth_loss: 0,
+        };
+        let mut version = None;
+        for (i, line) in s.lines().enumerate() {
+            let line_no = i + 1;
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let (&kind, rest) = match tokens.split_first() {
+                Some(split) => split,
+                None => continue,
+            };
+            let mut it = rest.iter().copied();
+            match kind {
+                "snapshot" => version = it.next().map(|v| v.to_string()),
+                "record" => {
+                    let src = Pos::new(parse(it.next(), line_no)?, parse(it.next(), line_no)?);
+                    let dest = Pos::new(parse(it.next(), line_no)?, parse(it.next(), line_no)?);
+                    let path = parse_path(it.next(), line_no)?;
+                    let response = parse(it.next(), line_no)?;
+                    snapshot.records.push(SnapshotRecord {
+                        query: Query { src, dest },
+                        path,
+                        response,
+                    });
+                }
+                "line" => {
+                    let num_parts: usize = parse(it.next(), line_no)?;
+                    let values: Vec<u32> = parse_all(&rest[1..], line_no)?;
+                    if num_parts == 0 || values.len() != 2 * num_parts {
+                        return Err(format!("line {}: expected {} parts", line_no, num_parts));
+                    }
+                    let parts = values
+                        .chunks(2)
+                        .map(|c| {
+                            if c[0] as usize >= GRID_LEN - 1 {
+                                return Err(format!("line {}: invalid part start", line_no));
+                            }
+                            Ok((c[0] as u8, c[1]))
+                        })
+                        .collect::<Result<_, String>>()?;
+                    snapshot.line_parts.push(parts);
+                }
+                "edges" => snapshot.edge_costs = parse_all(rest, line_no)?,
+                "bounds" => {
+                    snapshot.edge_cost_bounds =
+                        (parse(it.next(), line_no)?, parse(it.next(), line_no)?)
+                }
+                "steps" => {
+                    snapshot.steps = [parse(it.next(), line_no)?, parse(it.next(), line_no)?]
+                }
+                "totals" => snapshot.total_costs = parse_all(rest, line_no)?,
+                "loss" => {
+                    snapshot.loss = parse(it.next(), line_no)?;
+                    snapshot.smooth_loss = parse(it.next(), line_no)?;
+                }
+                _ => return Err(format!("line {}: unknown entry {}", line_no, kind)),
+            }
+        }
+        if version.as_deref() != Some(VERSION) {
+            return Err(format!("expected snapshot version {}", VERSION));
+        }
+        snapshot.validate()?;
+        Ok(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::tests::{answer_queries, test_params};
+    use crate::solver::{CostEstimator, GraphEstimator};
+
+    fn snapshot_after(turns: usize) -> Snapshot {
+        let mut estimator = GraphEstimator::new(&test_params());
+        answer_queries(&mut estimator, turns);
+        estimator.snapshot()
+    }
+
+    #[test]
+    fn round_trip() {
+        let snapshot = snapshot_after(20);
+        let text = snapshot.to_text();
+        let parsed: Snapshot = text.parse().unwrap();
+        assert_eq!(parsed.turn(), 20);
+        assert_eq!(parsed.to_text(), text);
+
+        let restored = GraphEstimator::from_snapshot(&parsed, &test_params()).unwrap();
+        assert_eq!(restored.snapshot().to_text(), text);
+    }
+
+    // Replaces the first line of `kind` in the text of `snapshot`
+    fn with_line(snapshot: &Snapshot, kind: &str, line: &str) -> String {
+        let text = snapshot.to_text();
+        let mut lines: Vec<&str> = text.lines().collect();
+        let i = lines.iter().position(|l| l.starts_with(kind)).unwrap();
+        lines[i] = line;
+        lines.join("\n")
+    }
+
+    #[test]
+    fn reject_invalid_part_starts() {
+        let snapshot = snapshot_after(5);
+        assert!(with_line(&snapshot, "line ", "line 2 0 5000 14 6000")
+            .parse::<Snapshot>()
+            .is_ok());
+        for line in &[
+            "line 0",
+            "line 2 1 5000 14 6000",
+            "line 2 0 5000 0 6000",
+            "line 3 0 5000 14 6000 14 7000",
+            "line 3 0 5000 14 6000 10 7000",
+            "line 2 0 5000 29 6000",
+            "line 2 0 5000 270 6000",
+            "line 5 0 1 5 1 10 1 15 1 20 1",
+        ] {
+            assert!(
+                with_line(&snapshot, "line ", line)
+                    .parse::<Snapshot>()
+                    .is_err(),
+                "{} should be rejected",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn reject_records_outside_the_grid() {
+        let snapshot = snapshot_after(5);
+        for record in &["record 30 0 0 0 UUU 3000", "record 0 0 0 30 RRR 3000"] {
+            assert!(with_line(&snapshot, "record ", record)
+                .parse::<Snapshot>()
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn reject_paths_not_reaching_dest() {
+        let snapshot = snapshot_after(5);
+        let record = &snapshot.records[0];
+        let (src, dest) = (record.query.src, record.query.dest);
+        let path: String = record.path.iter().map(|d| d.to_char()).collect();
+        let prefix = format!("record {} {} {} {}", src.r, src.c, dest.r, dest.c);
+        let reached = format!("{} {} {}", prefix, path, record.response);
+        assert!(with_line(&snapshot, "record ", &reached)
+            .parse::<Snapshot>()
+            .is_ok());
+        let short = format!("{} {} {}", prefix, &path[1..], record.response);
+        assert!(with_line(&snapshot, "record ", &short)
+            .parse::<Snapshot>()
+            .is_err());
+        let leaving = format!("record 0 0 0 1 UR {}", record.response);
+        assert!(with_line(&snapshot, "record ", &leaving)
+            .parse::<Snapshot>()
+            .is_err());
+    }
+
+    #[test]
+    fn reject_non_positive_costs() {
+        let mut snapshot = snapshot_after(5);
+        let base = snapshot.line_parts[0][0].1 as i32;
+        snapshot.edge_costs[0] = 1 - base;
+        assert!(snapshot.validate().is_ok());
+        snapshot.edge_costs[0] = -base;
+        assert!(snapshot.validate().is_err());
+        snapshot.edge_costs[0] = -5000;
+        assert!(snapshot.to_text().parse::<Snapshot>().is_err());
+        assert!(GraphEstimator::from_snapshot(&snapshot, &test_params()).is_err());
+    }
+
+    #[test]
+    fn from_snapshot_validates_parts() {
+        let mut snapshot = snapshot_after(5);
+        snapshot.line_parts[0] = vec![(0, 5000), (20, 6000), (10, 7000)];
+        assert!(GraphEstimator::from_snapshot(&snapshot, &test_params()).is_err());
+    }
+}
@@ -1,33 +1,86 @@
-use crate::algorithms::compute_shortest_path;
 use crate::algorithms::Graph;
+use crate::loss::Loss;
 use crate::models::*;
+use crate::params::{Budget, SolverParams, SplitSelection, StepMode, MAX_PARTS};
+use crate::policy::{standard_normal, PathSelector};
+use crate::priors;
+use crate::scheduler::TimeScheduler;
+use crate::snapshot::{Snapshot, SnapshotRecord};
 use rand::prelude::*;
-use rustc_hash::{FxHashMap, FxHashSet};
+use rand::rngs::SmallRng;
+use rustc_hash::FxHashMap;
 use std::convert::TryInto;
-use std::ops::Index;
-use std::time::{Duration, Instant};
+use std::time::Instant;
 
-const NORM_P: u32 = 2;
+/// Estimates edge costs from the responses to the paths answered so far.
+///
+/// `run_solver` routes every query on the current estimate, so any model
+/// implementing this trait can be plugged into it.
+pub trait CostEstimator: Graph<u32> {
+    fn new(params: &SolverParams) -> Self
+    where
+        Self: Sized;
 
-const LINE_COST_LB: i64 = 1000;
-const LINE_COST_UB: i64 = 9000;
-const EDGE_COST_LB: i64 = -400;
-const EDGE_COST_UB: i64 = 400;
+    /// Records the response to `path` and refines the estimate.
+    fn insert_new_record(&mut self, query: &Query, path: &[Dir], response: u32);
 
-const STEP: i64 = 50;
-const START_TEMP: f64 = 10000.0;
-const END_TEMP: f64 = 1.0;
+    /// Current value of the objective the estimator minimizes.
+    fn loss(&self) -> f64;
 
+    /// Variance of the estimated cost of `edge`, if the estimator tracks its uncertainty.
+    fn cost_variance(&self, _edge: EdgeIndex) -> Option<f64> {
+        None
+    }
+
+    /// Draws a plausible cost graph from the estimator's uncertainty.
+    ///
+    /// By default edges are sampled independently from a normal distribution
+    /// around the estimate, using `cost_variance`.
+    fn sample_costs<R: Rng>(&self, rng: &mut R) -> Option<GridGraph<u32>>
+    where
+        Self: Sized,
+    {
+        let mut graph = GridGraph::new(0);
+        for edge in EdgeIndex::iter() {
+            let sd = self.cost_variance(edge)?.max(0.0).sqrt();
+            let cost = self.get_cost(edge) as f64 + sd * standard_normal(rng);
+            graph[edge] = cost.round().max(1.0) as u32;
+        }
+        Some(graph)
+    }
+
+    /// Parts of `line` as (first x, base cost), if the estimator models them.
+    fn line_parts(&self, _line: LineIndex) -> Option<Vec<(u8, u32)>> {
+        None
+    }
+
+    /// The current estimate of every edge cost.
+    fn estimated_graph(&self) -> GridGraph<u32> {
+        let mut graph = GridGraph::new(0);
+        for edge in EdgeIndex::iter() {
+            graph[edge] = self.get_cost(edge);
+        }
+        graph
+    }
+
+    /// Checks internal caches against a recomputation. Only called in builds with logging.
+    fn validate_cache(&self) {}
+
+    /// Logs the final estimate.
+    fn log_summary(&self) {}
+}
+
+#[derive(Clone)]
 struct Record {
     query: Query,
     path: Vec<Dir>,
     response: u32,
-    visited: FxHashSet<EdgeIndex>,
+    visited: EdgeSet,
 }
 
 impl Record {
     fn new(query: &Query, path: &[Dir], response: u32) -> Self {
-        let mut visited = FxHashSet::default();
+        let mut visited = EdgeSet::new();
         let mut cur = query.src;
         for &dir in path {
             let edge = EdgeIndex::from_move(cur, dir);
@@ -37,66 +90,163 @@ impl Record {
         assert!(cur == query.dest);
         Record {
             query: query.clone(),
-            path: path.iter().cloned().collect(),
+            path: path.to_vec(),
             response,
             visited,
         }
     }
 }
 
-struct GraphEstimator {
-    line_costs: GridLines<[u32; 2]>,
+// Spreads turns over the seed space with `reseed_per_turn`
+const TURN_SEED_MUL: u64 = 0x9e37_79b9_7f4a_7c15;
+
+// Indices of the adaptive steps
+const LINE_STEP: usize = 0;
+const EDGE_STEP: usize = 1;
+// Relative change of an adaptive step per move
+const STEP_ADAPTATION: f64 = 0.01;
+const MAX_STEP_FACTOR: i64 = 10;
+// Records compared when looking for a large error
+const RESIDUAL_TOURNAMENT: usize = 4;
+
+// Range of the deviation amplitude D in generated instances
+const D_MIN: f64 = 100.0;
+const D_MAX: f64 = 2000.0;
+// Well-visited edges needed before D is estimated
+const MIN_EDGES_FOR_D: usize = 50;
+
+// Smallest x whose cumulative breakpoint prior reaches `q`
+fn breakpoint_quantile(q: f64) -> u8 {
+    if q <= 0.0 {
+        return 0;
+    }
+    let mut cumulative = 0.0;
+    for (x, &p) in priors::BREAKPOINT_HISTOGRAM.iter().enumerate() {
+        cumulative += p;
+        if cumulative >= q {
+            return x as u8;
+        }
+    }
+    GRID_LEN as u8 - 2
+}
+
+/// Part of a line with a single base cost, covering edges `start..end`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineSegment {
+    pub start: u8,
+    pub end: u8,
+    pub cost: u32,
+}
+
+/// Models each line as up to `MAX_PARTS` parts of constant cost plus a
+/// deviation per edge, fitted to the responses by simulated annealing.
+#[derive(Clone)]
+pub struct GraphEstimator {
+    line_costs: GridLines<[u32; MAX_PARTS]>,
     edge_costs: GridGraph<i32>,
-    mid_x: GridLines<u8>,
+    // part p of a line covers x in part_starts[p]..part_starts[p + 1] (or up to the end of the line)
+    part_starts: GridLines<[u8; MAX_PARTS]>,
+    num_parts: GridLines<u8>,
     records: Vec<Record>,
     // Cache for estimation
-    visit_counts: Vec<GridLines<[u32; 2]>>,
+    visit_counts: Vec<GridLines<[u32; MAX_PARTS]>>,
     total_costs: Vec<u32>,
-    visited_turns_per_line: FxHashMap<LineIndex, FxHashSet<u16>>,
-    visited_turns_per_edge: FxHashMap<EdgeIndex, FxHashSet<u16>>,
+    // turns visiting each line and edge in ascending order, by dense index
+    visited_turns_per_line: Vec<Vec<u16>>,
+    visited_turns_per_edge: Vec<Vec<u16>>,
     loss: i64,
-    time_limit: Duration,
+    // smoothness penalty between neighbouring edge deviations
+    smooth_loss: i64,
+    // penalty of each extra part, updated every turn
+    part_penalty: i64,
+    edge_cost_bounds: (i64, i64),
+    // steps of line and edge cost moves with `StepMode::Acceptance`
+    steps: [f64; 2],
+    params: SolverParams,
+    rng: SmallRng,
+    scheduler: TimeScheduler,
 }
 
 impl Graph<u32> for GraphEstimator {
     fn get_cost(&self, edge: EdgeIndex) -> u32 {
-        let mid_x = self.mid_x[edge.line];
-        let line_cost = if edge.x < mid_x {
-            self.line_costs[edge.line][0]
-        } else {
-            self.line_costs[edge.line][1]
-        } as i32;
+        let line_cost = self.line_costs[edge.line][self.part_of(edge.line, edge.x)] as i32;
         let edge_cost = self.edge_costs[edge];
-        let result = (line_cost + edge_cost).try_into().unwrap();
-        result
+        (line_cost + edge_cost).try_into().unwrap()
     }
 }
 
-impl GraphEstimator {
-    fn new(time_limit: Duration) -> GraphEstimator {
+impl CostEstimator for GraphEstimator {
+    fn new(params: &SolverParams) -> GraphEstimator {
+        assert!(
+            1 <= params.min_parts && params.min_parts <= params.max_parts,
+            "invalid range of parts: {}..={}",
+            params.min_parts,
+            params.max_parts
+        );
+        assert!(
+            params.max_parts <= MAX_PARTS,
+            "max_parts must be at most {}",
+            MAX_PARTS
+        );
+        let num_parts = 2.max(params.min_parts).min(params.max_parts);
+        let mut part_starts = [0; MAX_PARTS];
+        for (p, start) in part_starts.iter_mut().enumerate().take(num_parts) {
+            *start = if params.prior_init {
+                breakpoint_quantile(p as f64 / num_parts as f64)
+            } else {
+                (p * GRID_LEN / num_parts) as u8
+            };
+        }
+        let line_cost = if params.prior_init {
+            priors::LINE_COST_MEAN.round() as u32
+        } else {
+            params.line_cost_lb as u32
+        };
         GraphEstimator {
-            line_costs: GridLines::new([LINE_COST_LB as u32, LINE_COST_LB as u32]),
+            line_costs: GridLines::new([line_cost; MAX_PARTS]),
             edge_costs: GridGraph::new(0),
-            mid_x: GridLines::new(GRID_LEN as u8 / 2),
+            part_starts: GridLines::new(part_starts),
+            num_parts: GridLines::new(num_parts as u8),
             records: Vec::new(),
             visit_counts: Vec::new(),
             total_costs: Vec::new(),
-            visited_turns_per_line: FxHashMap::default(),
-            visited_turns_per_edge: FxHashMap::default(),
+            visited_turns_per_line: vec![Vec::new(); NUM_LINES],
+            visited_turns_per_edge: vec![Vec::new(); NUM_EDGES],
             loss: 0,
-            time_limit,
+            smooth_loss: 0,
+            part_penalty: 0,
+            edge_cost_bounds: if params.adaptive_d {
+                (-D_MAX as i64, D_MAX as i64)
+            } else {
+                (params.edge_cost_lb, params.edge_cost_ub)
+            },
+            steps: [params.step as f64; 2],
+            params: params.clone(),
+            rng: SmallRng::seed_from_u64(params.seed),
+            scheduler: TimeScheduler::new(params),
         }
     }
 
+    fn line_parts(&self, line: LineIndex) -> Option<Vec<(u8, u32)>> {
+        Some(
+            self.line_segments(line)
+                .iter()
+                .map(|s| (s.start, s.cost))
+                .collect(),
+        )
+    }
+
     fn validate_cache(&self) {
         assert!(self.records.len() == self.visit_counts.len());
         let turn = self.records.len();
         let mut actual_loss = 0i64;
         for i in 0..turn {
             let mut cost_sum = 0;
-            for &edge in &self.records[i].visited {
+            let mut visit_count = GridLines::new([0; MAX_PARTS]);
+            for edge in self.records[i].visited.iter() {
                 let cost = self.get_cost(edge);
                 cost_sum += cost;
+                visit_count[edge.line][self.part_of(edge.line, edge.x)] += 1;
             }
             assert!(
                 self.total_costs[i] == cost_sum,
@@ -105,253 +255,1055 @@ impl GraphEstimator {
                 self.total_costs[i],
                 cost_sum
             );
-            actual_loss += (cost_sum as i64 - self.records[i].response as i64)
-                .abs()
-                .pow(NORM_P);
+            for line in LineIndex::iter() {
+                assert!(
+                    self.visit_counts[i][line] == visit_count[line],
+                    "i={} line={:?} visit_counts={:?} actual={:?}",
+                    i,
+                    line,
+                    self.visit_counts[i][line],
+                    visit_count[line]
+                );
+            }
+            actual_loss += self.record_loss(cost_sum as i64, self.records[i].response as i64);
         }
         assert!(actual_loss == self.loss);
+        assert!(self.compute_smooth_loss() == self.smooth_loss);
     }
 
     fn insert_new_record(&mut self, query: &Query, path: &[Dir], response: u32) {
+        self.add_record(query, path, response);
+        self.update_estimation(1.0, 1.0);
+    }
+
+    fn loss(&self) -> f64 {
+        (self.loss + self.smooth_loss) as f64
+    }
+
+    fn log_summary(&self) {
+        debug!("line_costs={:?}", self.line_costs);
+        debug!("edge_costs={:?}", self.edge_costs);
+        debug!("num_parts={:?}", self.num_parts);
+        debug!("part_starts={:?}", self.part_starts);
+    }
+}
+
+impl GraphEstimator {
+    /// Adds a record and updates the caches without annealing
+    pub(crate) fn add_record(&mut self, query: &Query, path: &[Dir], response: u32) {
         let this_turn = self.records.len();
         self.records.push(Record::new(query, path, response));
 
-        let mut visit_count = GridLines::new([0; 2]);
+        let mut visit_count = GridLines::new([0; MAX_PARTS]);
         let mut total_cost = 0u32;
 
-        for &edge in &self.records[this_turn].visited {
+        for edge in self.records[this_turn].visited.iter() {
             let cost = self.get_cost(edge);
             total_cost += cost;
 
-            self.visited_turns_per_line
-                .entry(edge.line)
-                .or_default()
-                .insert(this_turn as u16);
-
-            self.visited_turns_per_edge
-                .entry(edge)
-                .or_default()
-                .insert(this_turn as u16);
-
-            if edge.x < self.mid_x[edge.line] {
-                visit_count[edge.line][0] += 1;
-            } else {
-                visit_count[edge.line][1] += 1;
+            let line_turns = &mut self.visited_turns_per_line[edge.line.as_usize()];
+            if line_turns.last() != Some(&(this_turn as u16)) {
+                line_turns.push(this_turn as u16);
             }
+            self.visited_turns_per_edge[edge.as_usize()].push(this_turn as u16);
+
+            visit_count[edge.line][self.part_of(edge.line, edge.x)] += 1;
         }
 
-        self.loss += (total_cost as i64 - self.records[this_turn].response as i64)
-            .abs()
-            .pow(NORM_P);
+        self.loss += self.record_loss(total_cost as i64, self.records[this_turn].response as i64);
         self.visit_counts.push(visit_count);
         self.total_costs.push(total_cost);
+    }
 
-        self.update_estimation();
+    /// Parts of `line` in order
+    pub fn line_segments(&self, line: LineIndex) -> Vec<LineSegment> {
+        (0..self.num_parts[line] as usize)
+            .map(|p| LineSegment {
+                start: self.part_starts[line][p],
+                end: self.part_end(line, p),
+                cost: self.line_costs[line][p],
+            })
+            .collect()
     }
 
-    fn update_estimation(&mut self) {
-        let start = Instant::now();
-        let time_limit = self.time_limit * 90 / 100 / 1000;
+    /// Deviation of `edge` from the cost of its part
+    pub fn edge_deviation(&self, edge: EdgeIndex) -> i32 {
+        self.edge_costs[edge]
+    }
 
-        let mut loops = 0;
-        let mut updates_type0 = 0;
-        let mut updates_type1 = 0;
-        let mut updates_type2 = 0;
-        let start_loss = self.loss;
+    /// Estimated length minus response of each record, in turn order
+    pub fn residuals(&self) -> Vec<i64> {
+        self.records
+            .iter()
+            .zip(&self.total_costs)
+            .map(|(r, &total)| total as i64 - r.response as i64)
+            .collect()
+    }
 
-        loop {
-            let elapsed = start.elapsed();
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            records: self
+                .records
+                .iter()
+                .map(|r| SnapshotRecord {
+                    query: r.query.clone(),
+                    path: r.path.clone(),
+                    response: r.response,
+                })
+                .collect(),
+            line_parts: (0..NUM_LINES)
+                .map(|i| self.line_parts(LineIndex::from_usize(i)).unwrap())
+                .collect(),
+            edge_costs: (0..NUM_EDGES)
+                .map(|i| self.edge_costs[EdgeIndex::from_usize(i)])
+                .collect(),
+            edge_cost_bounds: self.edge_cost_bounds,
+            steps: self.steps,
+            total_costs: self.total_costs.clone(),
+            loss: self.loss,
+            smooth_loss: self.smooth_loss,
+        }
+    }
 
-            let ratio = elapsed.as_secs_f64() / time_limit.as_secs_f64();
+    /// Restores the state saved by `snapshot`. The caches are rebuilt from the
+    /// records and checked against the saved ones.
+    pub fn from_snapshot(
+        snapshot: &Snapshot,
+        params: &SolverParams,
+    ) -> Result<GraphEstimator, String> {
+        snapshot.validate()?;
+        let mut estimator = GraphEstimator::new(params);
+        for (i, parts) in snapshot.line_parts.iter().enumerate() {
+            let line = LineIndex::from_usize(i);
+            estimator.num_parts[line] = parts.len() as u8;
+            for (p, &(start, cost)) in parts.iter().enumerate() {
+                estimator.part_starts[line][p] = start;
+                estimator.line_costs[line][p] = cost;
+            }
+        }
+        for (i, &cost) in snapshot.edge_costs.iter().enumerate() {
+            estimator.edge_costs[EdgeIndex::from_usize(i)] = cost;
+        }
+        estimator.edge_cost_bounds = snapshot.edge_cost_bounds;
+        estimator.steps = snapshot.steps;
+        estimator.smooth_loss = estimator.compute_smooth_loss();
+        for r in &snapshot.records {
+            estimator.add_record(&r.query, &r.path, r.response);
+        }
+        if estimator.total_costs != snapshot.total_costs
+            || estimator.loss != snapshot.loss
+            || estimator.smooth_loss != snapshot.smooth_loss
+        {
+            return Err("cached totals do not match the restored state".to_string());
+        }
+        Ok(estimator)
+    }
 
-            if ratio >= 1.0 {
-                break;
+    /// Replaces the state by a copy of `other`, keeping the own random number generator
+    #[cfg(feature = "parallel")]
+    pub(crate) fn restart_from(&mut self, other: &GraphEstimator) {
+        let rng = self.rng.clone();
+        *self = other.clone();
+        self.rng = rng;
+    }
+
+    fn part_of(&self, line: LineIndex, x: u8) -> usize {
+        let starts = &self.part_starts[line];
+        (1..self.num_parts[line] as usize)
+            .rev()
+            .find(|&p| x >= starts[p])
+            .unwrap_or(0)
+    }
+
+    // Exclusive end of `part`
+    fn part_end(&self, line: LineIndex, part: usize) -> u8 {
+        if part + 1 < self.num_parts[line] as usize {
+            self.part_starts[line][part + 1]
+        } else {
+            GRID_LEN as u8 - 1
+        }
+    }
+
+    fn smooth_term(&self, a: i64, b: i64) -> i64 {
+        (self.params.smooth_weight * ((a - b) as f64).powi(2)).round() as i64
+    }
+
+    // Neighbours of `edge` on the same line
+    fn line_neighbours(edge: EdgeIndex) -> impl Iterator<Item = EdgeIndex> {
+        let prev = edge.x.checked_sub(1);
+        let next = Some(edge.x + 1).filter(|&x| (x as usize) < GRID_LEN - 1);
+        prev.into_iter()
+            .chain(next)
+            .map(move |x| EdgeIndex::new(edge.line, x))
+    }
+
+    // Change of the smoothness penalty when the deviation of `edge` becomes `next_cost`
+    fn smooth_diff(&self, edge: EdgeIndex, next_cost: i64) -> i64 {
+        if self.params.smooth_weight == 0.0 {
+            return 0;
+        }
+        let cur_cost = self.edge_costs[edge] as i64;
+        Self::line_neighbours(edge)
+            .map(|n| {
+                let neighbour = self.edge_costs[n] as i64;
+                self.smooth_term(next_cost, neighbour) - self.smooth_term(cur_cost, neighbour)
+            })
+            .sum()
+    }
+
+    fn compute_smooth_loss(&self) -> i64 {
+        EdgeIndex::iter()
+            .filter(|e| (e.x as usize) + 1 < GRID_LEN - 1)
+            .map(|e| {
+                let next = EdgeIndex::new(e.line, e.x + 1);
+                self.smooth_term(self.edge_costs[e] as i64, self.edge_costs[next] as i64)
+            })
+            .sum()
+    }
+
+    fn edge_bound_excess(&self, cost: i64) -> i64 {
+        let (lb, ub) = self.edge_cost_bounds;
+        (lb - cost).max(cost - ub).max(0)
+    }
+
+    // Edges left outside by shrinking bounds may still move back in. Deviations
+    // stay above `-line_cost_lb` so that every line cost keeps edge costs positive.
+    fn edge_cost_allowed(&self, cur_cost: i64, next_cost: i64) -> bool {
+        self.edge_bound_excess(next_cost) <= self.edge_bound_excess(cur_cost)
+            && next_cost + self.params.line_cost_lb >= 1
+    }
+
+    /// Estimates the deviation amplitude `D` from the edges visited by at least
+    /// `d_min_visits` records, assuming deviations uniform in `[-D, D]`.
+    fn estimate_d(&self) -> Option<f64> {
+        let mut count = 0;
+        let mut square_sum = 0.0;
+        for (i, turns) in self.visited_turns_per_edge.iter().enumerate() {
+            if turns.len() >= self.params.d_min_visits {
+                count += 1;
+                square_sum += (self.edge_costs[EdgeIndex::from_usize(i)] as f64).powi(2);
             }
+        }
+        if count < MIN_EDGES_FOR_D {
+            return None;
+        }
+        Some((3.0 * square_sum / count as f64).sqrt())
+    }
 
-            let temp = START_TEMP + (END_TEMP - START_TEMP) * ratio;
+    fn update_edge_cost_bounds(&mut self) {
+        if let Some(d) = self.estimate_d() {
+            let bound = (d * self.params.d_margin).max(D_MIN).min(D_MAX) as i64;
+            self.edge_cost_bounds = (-bound, bound);
+            trace!("Estimated d={:.0} bound={}", d, bound);
+        }
+    }
 
-            loops += 1;
-            let mut rng = thread_rng();
-            let update_type = rng.gen_range(0, 3);
-            if update_type == 0 {
-                let line = LineIndex::choose(&mut rng);
-                let part = rng.gen_range(0, 2);
-                let sign: i64 = if rng.gen::<bool>() { 1 } else { -1 };
-                let cur_cost = self.line_costs[line][part];
-                let next_cost = cur_cost as i64 + sign * STEP;
-                if next_cost < LINE_COST_LB || next_cost > LINE_COST_UB {
-                    continue;
-                }
+    fn record_loss(&self, total_cost: i64, response: i64) -> i64 {
+        self.params
+            .loss
+            .eval(self.params.error_metric, total_cost, response)
+    }
 
-                let mut loss_diff = 0i64;
-                let mut loss_diff_updated = false;
-
-                if let Some(turns) = self.visited_turns_per_line.get(&line) {
-                    for &turn in turns {
-                        let turn = turn as usize;
-                        let visit_counts = &self.visit_counts[turn];
-                        let visit_count = visit_counts[line][part];
-
-                        if visit_count == 0 {
-                            continue;
-                        }
-
-                        let response = self.records[turn].response as i64;
-                        let cur_total_cost = self.total_costs[turn] as i64;
-                        let new_total_cost =
-                            self.total_costs[turn] as i64 + sign * STEP * visit_count as i64;
-                        loss_diff -= (cur_total_cost - response).abs().pow(NORM_P);
-                        loss_diff += (new_total_cost - response).abs().pow(NORM_P);
-                        loss_diff_updated = true;
-                    }
-                }
+    // Change of the loss when the total cost of `turn` changes by `cost_diff`
+    fn loss_diff_of(&self, turn: usize, cost_diff: i64) -> i64 {
+        let response = self.records[turn].response as i64;
+        let total_cost = self.total_costs[turn] as i64;
+        self.record_loss(total_cost + cost_diff, response) - self.record_loss(total_cost, response)
+    }
 
-                if !loss_diff_updated {
-                    continue;
-                }
+    // Change of the loss when the total costs change by `cost_diffs` per turn
+    fn loss_diff_of_turns(&self, cost_diffs: &FxHashMap<usize, i64>) -> i64 {
+        cost_diffs
+            .iter()
+            .map(|(&turn, &diff)| self.loss_diff_of(turn, diff))
+            .sum()
+    }
 
-                let prob = (-loss_diff as f64 / temp).exp();
-                if rng.gen::<f64>() < prob {
-                    self.line_costs[line][part] = next_cost as u32;
-                    self.loss += loss_diff;
-                    if let Some(turns) = self.visited_turns_per_line.get(&line) {
-                        for &turn in turns {
-                            let visit_count = &self.visit_counts[turn as usize];
-                            let new_total_cost = self.total_costs[turn as usize] as i64
-                                + sign * STEP * visit_count[line][part] as i64;
-                            self.total_costs[turn as usize] = new_total_cost as u32;
-                        }
-                    }
-                    updates_type0 += 1;
-                }
-            } else if update_type == 1 {
-                let line = LineIndex::choose(&mut rng);
-                let sign: i8 = if rng.gen::<bool>() { 1 } else { -1 };
-                let cur_mid_x = self.mid_x[line];
-                let next_mid_x = self.mid_x[line] as i8 + sign;
-                if next_mid_x <= 0 || next_mid_x >= GRID_LEN as i8 - 1 {
-                    continue;
+    fn apply_cost_diffs(&mut self, cost_diffs: FxHashMap<usize, i64>) {
+        for (turn, diff) in cost_diffs {
+            self.total_costs[turn] = (self.total_costs[turn] as i64 + diff) as u32;
+        }
+    }
+
+    fn accept(&mut self, loss_diff: i64, temp: f64) -> bool {
+        let prob = (-loss_diff as f64 / temp).exp();
+        self.rng.gen::<f64>() < prob
+    }
+
+    // Step of a line (`LINE_STEP`) or edge (`EDGE_STEP`) cost move at temperature `temp`
+    fn step(&self, kind: usize, temp: f64) -> i64 {
+        let step = match self.params.step_mode {
+            StepMode::Fixed => self.params.step as f64,
+            StepMode::Temperature => {
+                self.params.step as f64 * (temp / self.params.start_temp).max(0.0).sqrt()
+            }
+            StepMode::Acceptance => self.steps[kind],
+        };
+        (step.round() as i64).max(1)
+    }
+
+    // Grows the step after acceptances and shrinks it after rejections so that
+    // the acceptance rate settles at `step_target_acceptance`
+    fn adapt_step(&mut self, kind: usize, accepted: bool) {
+        if self.params.step_mode != StepMode::Acceptance {
+            return;
+        }
+        let target = self.params.step_target_acceptance;
+        let factor = if accepted {
+            1.0 + STEP_ADAPTATION * (1.0 - target)
+        } else {
+            1.0 - STEP_ADAPTATION * target
+        };
+        let max_step = (self.params.step * MAX_STEP_FACTOR) as f64;
+        self.steps[kind] = (self.steps[kind] * factor).max(1.0).min(max_step);
+    }
+
+    /// With probability `residual_target_prob`, picks a random edge of the record
+    /// with the largest error among a few random ones, together with the sign
+    /// of a move that reduces that error.
+    fn choose_high_residual_edge(&mut self) -> Option<(EdgeIndex, i64)> {
+        if self.params.residual_target_prob <= 0.0
+            || self.records.is_empty()
+            || self.rng.gen::<f64>() >= self.params.residual_target_prob
+        {
+            return None;
+        }
+        let mut worst = None;
+        for _ in 0..RESIDUAL_TOURNAMENT {
+            let turn = self.rng.gen_range(0, self.records.len());
+            let residual = self.total_costs[turn] as i64 - self.records[turn].response as i64;
+            if worst.map_or(true, |(_, r): (usize, i64)| residual.abs() > r.abs()) {
+                worst = Some((turn, residual));
+            }
+        }
+        let (turn, residual) = worst.unwrap();
+        let visited = &self.records[turn].visited;
+        let edge = visited
+            .iter()
+            .nth(self.rng.gen_range(0, visited.len()))
+            .unwrap();
+        Some((edge, if residual > 0 { -1 } else { 1 }))
+    }
+
+    fn random_sign(&mut self) -> i64 {
+        if self.rng.gen::<bool>() {
+            1
+        } else {
+            -1
+        }
+    }
+
+    /// Moves the cost of a single part of a line by one step, or by a random
+    /// jump of up to `line_jump`
+    fn move_line_cost(&mut self, temp: f64) -> bool {
+        let (line, part, sign) = match self.choose_high_residual_edge() {
+            Some((edge, sign)) => (edge.line, self.part_of(edge.line, edge.x), sign),
+            None => {
+                let line = LineIndex::choose(&mut self.rng);
+                let part = self.rng.gen_range(0, self.num_parts[line] as usize);
+                (line, part, self.random_sign())
+            }
+        };
+        let jump = self.params.line_jump >= 1
+            && self.params.line_jump_prob > 0.0
+            && self.rng.gen::<f64>() < self.params.line_jump_prob;
+        let cost_diff = if jump {
+            sign * self.rng.gen_range(1, self.params.line_jump + 1)
+        } else {
+            sign * self.step(LINE_STEP, temp)
+        };
+        let next_cost = self.line_costs[line][part] as i64 + cost_diff;
+        if !(self.params.line_cost_lb..=self.params.line_cost_ub).contains(&next_cost) {
+            return false;
+        }
+
+        let mut loss_diff = 0i64;
+        let mut loss_diff_updated = false;
+        for &turn in &self.visited_turns_per_line[line.as_usize()] {
+            let visit_count = self.visit_counts[turn as usize][line][part];
+            if visit_count == 0 {
+                continue;
+            }
+            loss_diff += self.loss_diff_of(turn as usize, cost_diff * visit_count as i64);
+            loss_diff_updated = true;
+        }
+        if !loss_diff_updated {
+            return false;
+        }
+        let accepted = self.accept(loss_diff, temp);
+        if !jump {
+            self.adapt_step(LINE_STEP, accepted);
+        }
+        if !accepted {
+            return false;
+        }
+
+        self.line_costs[line][part] = next_cost as u32;
+        self.loss += loss_diff;
+        for &turn in &self.visited_turns_per_line[line.as_usize()] {
+            let turn = turn as usize;
+            let visit_count = self.visit_counts[turn][line][part];
+            self.total_costs[turn] =
+                (self.total_costs[turn] as i64 + cost_diff * visit_count as i64) as u32;
+        }
+        true
+    }
+
+    /// Shifts the start of a part by one edge
+    fn move_breakpoint(&mut self, temp: f64) -> bool {
+        let line = LineIndex::choose(&mut self.rng);
+        let num_parts = self.num_parts[line] as usize;
+        if num_parts < 2 {
+            return false;
+        }
+        let part = self.rng.gen_range(1, num_parts);
+        let sign = self.random_sign();
+        let cur_start = self.part_starts[line][part];
+        let next_start = cur_start as i64 + sign;
+        if next_start <= self.part_starts[line][part - 1] as i64
+            || next_start >= self.part_end(line, part) as i64
+        {
+            return false;
+        }
+        let next_start = next_start as u8;
+
+        // sign == +1 -> edge at cur_start moves from `part` to `part - 1`
+        // sign == -1 -> edge at next_start moves from `part - 1` to `part`
+        let edge = if sign == 1 {
+            EdgeIndex::new(line, cur_start)
+        } else {
+            EdgeIndex::new(line, next_start)
+        };
+        let (old_part, new_part) = if sign == 1 {
+            (part, part - 1)
+        } else {
+            (part - 1, part)
+        };
+        let cost_diff =
+            self.line_costs[line][new_part] as i64 - self.line_costs[line][old_part] as i64;
+
+        let mut loss_diff = 0i64;
+        for &turn in &self.visited_turns_per_line[line.as_usize()] {
+            let turn = turn as usize;
+            if !self.records[turn].visited.contains(edge) {
+                continue;
+            }
+            loss_diff += self.loss_diff_of(turn, cost_diff);
+        }
+        if !self.accept(loss_diff, temp) {
+            return false;
+        }
+
+        for &turn in &self.visited_turns_per_line[line.as_usize()] {
+            let turn = turn as usize;
+            if !self.records[turn].visited.contains(edge) {
+                continue;
+            }
+            assert!(
+                self.visit_counts[turn][line][old_part] > 0,
+                "{:?} {:?}",
+                self.visit_counts[turn][line],
+                self.part_starts[line],
+            );
+            self.visit_counts[turn][line][old_part] -= 1;
+            self.visit_counts[turn][line][new_part] += 1;
+            self.total_costs[turn] = (self.total_costs[turn] as i64 + cost_diff)
+                .try_into()
+                .unwrap();
+        }
+        self.part_starts[line][part] = next_start;
+        self.loss += loss_diff;
+        true
+    }
+
+    /// Moves the deviation of a single edge by one step
+    fn move_edge_cost(&mut self, temp: f64) -> bool {
+        let (edge, sign) = match self.choose_high_residual_edge() {
+            Some(target) => target,
+            None => {
+                let edge = EdgeIndex::choose(&mut self.rng);
+                (edge, self.random_sign())
+            }
+        };
+        let cost_diff = sign * self.step(EDGE_STEP, temp);
+        let cur_cost = self.edge_costs[edge] as i64;
+        let next_cost = cur_cost + cost_diff;
+        if !self.edge_cost_allowed(cur_cost, next_cost) {
+            return false;
+        }
+
+        let mut loss_diff = 0i64;
+        let mut loss_diff_updated = false;
+        for &turn in &self.visited_turns_per_edge[edge.as_usize()] {
+            loss_diff += self.loss_diff_of(turn as usize, cost_diff);
+            loss_diff_updated = true;
+        }
+        // with smoothing, unvisited edges follow their neighbours
+        let smooth_diff = self.smooth_diff(edge, next_cost);
+        if !loss_diff_updated && self.params.smooth_weight == 0.0 {
+            return false;
+        }
+        let accepted = self.accept(loss_diff + smooth_diff, temp);
+        self.adapt_step(EDGE_STEP, accepted);
+        if !accepted {
+            return false;
+        }
+
+        self.edge_costs[edge] = next_cost as i32;
+        self.loss += loss_diff;
+        self.smooth_loss += smooth_diff;
+        for &turn in &self.visited_turns_per_edge[edge.as_usize()] {
+            let turn = turn as usize;
+            self.total_costs[turn] = (self.total_costs[turn] as i64 + cost_diff) as u32;
+        }
+        true
+    }
+
+    /// Splits a part in two at a random edge. The new right part starts one step
+    /// away from the cost of the split part, or with split selection, at the cost
+    /// fitted to the residuals of the records visiting it.
+    fn add_breakpoint(&mut self, temp: f64) -> bool {
+        let line = LineIndex::choose(&mut self.rng);
+        let num_parts = self.num_parts[line] as usize;
+        if num_parts >= self.params.max_parts {
+            return false;
+        }
+        let start = self.rng.gen_range(1, GRID_LEN as u8 - 1);
+        let part = self.part_of(line, start);
+        if self.part_starts[line][part] == start {
+            return false;
+        }
+        let end = self.part_end(line, part);
+        let cur_cost = self.line_costs[line][part] as i64;
+        let step_cost = if self.params.split_selection == SplitSelection::Free {
+            let next_cost = cur_cost + self.random_sign() * self.params.step;
+            if !(self.params.line_cost_lb..=self.params.line_cost_ub).contains(&next_cost) {
+                return false;
+            }
+            Some(next_cost)
+        } else {
+            None
+        };
+
+        // (turn, visits of the new part)
+        let mut moved_visits = Vec::new();
+        for &turn in &self.visited_turns_per_line[line.as_usize()] {
+            let turn = turn as usize;
+            let visited = &self.records[turn].visited;
+            let count = (start..end)
+                .filter(|&x| visited.contains(EdgeIndex::new(line, x)))
+                .count() as u32;
+            if count > 0 {
+                moved_visits.push((turn, count));
+            }
+        }
+
+        let next_cost = match step_cost {
+            Some(next_cost) => next_cost,
+            None => self.fitted_split_cost(cur_cost, &moved_visits),
+        };
+        let cost_diff = next_cost - cur_cost;
+
+        let loss_diff: i64 = moved_visits
+            .iter()
+            .map(|&(turn, count)| self.loss_diff_of(turn, cost_diff * count as i64))
+            .sum();
+        if !self.accept(loss_diff + self.part_penalty, temp) {
+            return false;
+        }
+
+        for q in (part + 1..num_parts).rev() {
+            self.part_starts[line][q + 1] = self.part_starts[line][q];
+            self.line_costs[line][q + 1] = self.line_costs[line][q];
+        }
+        self.part_starts[line][part + 1] = start;
+        self.line_costs[line][part + 1] = next_cost as u32;
+        for &turn in &self.visited_turns_per_line[line.as_usize()] {
+            let counts = &mut self.visit_counts[turn as usize][line];
+            for q in (part + 1..num_parts).rev() {
+                counts[q + 1] = counts[q];
+            }
+            counts[part + 1] = 0;
+        }
+        for (turn, count) in moved_visits {
+            let counts = &mut self.visit_counts[turn][line];
+            counts[part] -= count;
+            counts[part + 1] = count;
+            self.total_costs[turn] =
+                (self.total_costs[turn] as i64 + cost_diff * count as i64) as u32;
+        }
+        self.num_parts[line] += 1;
+        self.loss += loss_diff;
+        true
+    }
+
+    // Least-squares cost of a new part split from one at `cur_cost`, or a single
+    // step away if it has never been visited
+    fn fitted_split_cost(&mut self, cur_cost: i64, moved_visits: &[(usize, u32)]) -> i64 {
+        let (mut cr, mut cc) = (0i64, 0i64);
+        for &(turn, count) in moved_visits {
+            let residual = self.records[turn].response as i64 - self.total_costs[turn] as i64;
+            cr += count as i64 * residual;
+            cc += count as i64 * count as i64;
+        }
+        let cost_diff = if cc == 0 {
+            self.random_sign() * self.params.step
+        } else {
+            cr / cc
+        };
+        (cur_cost + cost_diff)
+            .max(self.params.line_cost_lb)
+            .min(self.params.line_cost_ub)
+    }
+
+    /// Merges two neighbouring parts, keeping the cost of one of them
+    fn remove_breakpoint(&mut self, temp: f64) -> bool {
+        let line = LineIndex::choose(&mut self.rng);
+        let num_parts = self.num_parts[line] as usize;
+        if num_parts <= self.params.min_parts {
+            return false;
+        }
+        let part = self.rng.gen_range(1, num_parts);
+        // the part whose cost is overwritten by the merge
+        let (kept, dropped) = if self.rng.gen::<bool>() {
+            (part - 1, part)
+        } else {
+            (part, part - 1)
+        };
+        let cost_diff = self.line_costs[line][kept] as i64 - self.line_costs[line][dropped] as i64;
+
+        let mut loss_diff = 0i64;
+        for &turn in &self.visited_turns_per_line[line.as_usize()] {
+            let visit_count = self.visit_counts[turn as usize][line][dropped];
+            if visit_count == 0 {
+                continue;
+            }
+            loss_diff += self.loss_diff_of(turn as usize, cost_diff * visit_count as i64);
+        }
+        if !self.accept(loss_diff - self.part_penalty, temp) {
+            return false;
+        }
+
+        for &turn in &self.visited_turns_per_line[line.as_usize()] {
+            let turn = turn as usize;
+            let counts = &mut self.visit_counts[turn][line];
+            let visit_count = counts[dropped];
+            counts[part - 1] += counts[part];
+            for q in part..num_parts - 1 {
+                counts[q] = counts[q + 1];
+            }
+            counts[num_parts - 1] = 0;
+            self.total_costs[turn] =
+                (self.total_costs[turn] as i64 + cost_diff * visit_count as i64) as u32;
+        }
+        self.line_costs[line][part - 1] = self.line_costs[line][kept];
+        for q in part..num_parts - 1 {
+            self.part_starts[line][q] = self.part_starts[line][q + 1];
+            self.line_costs[line][q] = self.line_costs[line][q + 1];
+        }
+        self.part_starts[line][num_parts - 1] = 0;
+        self.line_costs[line][num_parts - 1] = self.params.line_cost_lb as u32;
+        self.num_parts[line] -= 1;
+        self.loss += loss_diff;
+        true
+    }
+
+    /// Shifts the start of a part by one edge and moves the costs of both parts
+    /// next to it by up to a step each
+    fn move_breakpoint_rebalance(&mut self, temp: f64) -> bool {
+        let line = LineIndex::choose(&mut self.rng);
+        let num_parts = self.num_parts[line] as usize;
+        if num_parts < 2 {
+            return false;
+        }
+        let part = self.rng.gen_range(1, num_parts);
+        let sign = self.random_sign();
+        let cur_start = self.part_starts[line][part];
+        let next_start = cur_start as i64 + sign;
+        if next_start <= self.part_starts[line][part - 1] as i64
+            || next_start >= self.part_end(line, part) as i64
+        {
+            return false;
+        }
+        let next_start = next_start as u8;
+        let (edge, old_part, new_part) = if sign == 1 {
+            (EdgeIndex::new(line, cur_start), part, part - 1)
+        } else {
+            (EdgeIndex::new(line, next_start), part - 1, part)
+        };
+
+        let step = self.step(LINE_STEP, temp);
+        let mut next_costs = [0i64; 2];
+        for (i, next_cost) in next_costs.iter_mut().enumerate() {
+            *next_cost =
+                self.line_costs[line][part - 1 + i] as i64 + self.rng.gen_range(-step, step + 1);
+            if !(self.params.line_cost_lb..=self.params.line_cost_ub).contains(next_cost) {
+                return false;
+            }
+        }
+
+        let mut cost_diffs = FxHashMap::default();
+        for &turn in &self.visited_turns_per_line[line.as_usize()] {
+            let turn = turn as usize;
+            let mut counts = self.visit_counts[turn][line];
+            let cur_total: i64 = (part - 1..=part)
+                .map(|q| counts[q] as i64 * self.line_costs[line][q] as i64)
+                .sum();
+            if self.records[turn].visited.contains(edge) {
+                counts[old_part] -= 1;
+                counts[new_part] += 1;
+            }
+            let next_total: i64 = (part - 1..=part)
+                .map(|q| counts[q] as i64 * next_costs[q + 1 - part])
+                .sum();
+            if next_total != cur_total {
+                cost_diffs.insert(turn, next_total - cur_total);
+            }
+        }
+        let loss_diff = self.loss_diff_of_turns(&cost_diffs);
+        if !self.accept(loss_diff, temp) {
+            return false;
+        }
+
+        for &turn in &self.visited_turns_per_line[line.as_usize()] {
+            let turn = turn as usize;
+            if self.records[turn].visited.contains(edge) {
+                self.visit_counts[turn][line][old_part] -= 1;
+                self.visit_counts[turn][line][new_part] += 1;
+            }
+        }
+        self.apply_cost_diffs(cost_diffs);
+        self.part_starts[line][part] = next_start;
+        self.line_costs[line][part - 1] = next_costs[0] as u32;
+        self.line_costs[line][part] = next_costs[1] as u32;
+        self.loss += loss_diff;
+        true
+    }
+
+    /// Moves cost from the deviations of the edges of a part into the part cost
+    /// or back. No path length changes, so only the edge bounds and the
+    /// smoothness penalty at the ends of the part decide.
+    fn transfer_line_edge_cost(&mut self, temp: f64) -> bool {
+        let line = LineIndex::choose(&mut self.rng);
+        let part = self.rng.gen_range(0, self.num_parts[line] as usize);
+        let cost_diff = self.random_sign() * self.step(LINE_STEP, temp);
+        let next_cost = self.line_costs[line][part] as i64 + cost_diff;
+        if !(self.params.line_cost_lb..=self.params.line_cost_ub).contains(&next_cost) {
+            return false;
+        }
+        let (start, end) = (self.part_starts[line][part], self.part_end(line, part));
+        for x in start..end {
+            let cur_cost = self.edge_costs[EdgeIndex::new(line, x)] as i64;
+            if !self.edge_cost_allowed(cur_cost, cur_cost - cost_diff) {
+                return false;
+            }
+        }
+
+        // differences inside the part are unchanged
+        let mut smooth_diff = 0;
+        if self.params.smooth_weight != 0.0 {
+            let mut boundaries = Vec::new();
+            if start > 0 {
+                boundaries.push((start - 1, start));
+            }
+            if (end as usize) < GRID_LEN - 1 {
+                boundaries.push((end, end - 1));
+            }
+            for (outer, inner) in boundaries {
+                let outer = self.edge_costs[EdgeIndex::new(line, outer)] as i64;
+                let inner = self.edge_costs[EdgeIndex::new(line, inner)] as i64;
+                smooth_diff +=
+                    self.smooth_term(outer, inner - cost_diff) - self.smooth_term(outer, inner);
+            }
+        }
+        if !self.accept(smooth_diff, temp) {
+            return false;
+        }
+
+        self.line_costs[line][part] = next_cost as u32;
+        for x in start..end {
+            self.edge_costs[EdgeIndex::new(line, x)] -= cost_diff as i32;
+        }
+        self.smooth_loss += smooth_diff;
+        true
+    }
+
+    /// Moves a step of cost from one part to another, both visited by a random record
+    fn trade_line_costs(&mut self, temp: f64) -> bool {
+        if self.records.is_empty() {
+            return false;
+        }
+        let turn = self.rng.gen_range(0, self.records.len());
+        let visited = &self.records[turn].visited;
+        let a = visited
+            .iter()
+            .nth(self.rng.gen_range(0, visited.len()))
+            .unwrap();
+        let b = visited
+            .iter()
+            .nth(self.rng.gen_range(0, visited.len()))
+            .unwrap();
+        let (part_a, part_b) = (self.part_of(a.line, a.x), self.part_of(b.line, b.x));
+        if a.line == b.line && part_a == part_b {
+            return false;
+        }
+
+        let cost_diff = self.random_sign() * self.step(LINE_STEP, temp);
+        let moves = [(a.line, part_a, cost_diff), (b.line, part_b, -cost_diff)];
+        for &(line, part, diff) in &moves {
+            let next_cost = self.line_costs[line][part] as i64 + diff;
+            if !(self.params.line_cost_lb..=self.params.line_cost_ub).contains(&next_cost) {
+                return false;
+            }
+        }
+
+        let mut cost_diffs = FxHashMap::default();
+        for &(line, part, diff) in &moves {
+            for &turn in &self.visited_turns_per_line[line.as_usize()] {
+                let visit_count = self.visit_counts[turn as usize][line][part];
+                if visit_count > 0 {
+                    *cost_diffs.entry(turn as usize).or_insert(0) += diff * visit_count as i64;
                 }
-                let next_mid_x = next_mid_x as u8;
-
-                // sign == +1 -> cur_mid_x moves from part 1 to part 0
-                // sign == -1 => new_mid_x moves from part 0 to part 1
-                let edge = if sign == 1 {
-                    EdgeIndex::new(line, cur_mid_x)
-                } else {
-                    EdgeIndex::new(line, next_mid_x)
-                };
-                let (old_part, new_part) = if sign == 1 { (1, 0) } else { (0, 1) };
-
-                let mut loss_diff = 0i64;
-                if let Some(turns) = self.visited_turns_per_line.get(&line) {
-                    for &turn in turns {
-                        let turn = turn as usize;
-                        if !self.records[turn].visited.contains(&edge) {
-                            continue;
-                        }
-
-                        let response = self.records[turn as usize].response as i64;
-                        let cur_total_cost = self.total_costs[turn as usize] as i64;
-                        let cost_diff = self.line_costs[line][new_part] as i64
-                            - self.line_costs[line][old_part] as i64;
-
-                        loss_diff -= (cur_total_cost - response).abs().pow(NORM_P);
-                        loss_diff += (cur_total_cost + cost_diff - response).abs().pow(NORM_P);
-                    }
+            }
+        }
+        let loss_diff = self.loss_diff_of_turns(&cost_diffs);
+        if !self.accept(loss_diff, temp) {
+            return false;
+        }
+
+        for &(line, part, diff) in &moves {
+            self.line_costs[line][part] = (self.line_costs[line][part] as i64 + diff) as u32;
+        }
+        self.apply_cost_diffs(cost_diffs);
+        self.loss += loss_diff;
+        true
+    }
+
+    // Visit-weighted average of the part costs of `line`
+    fn merged_cost(&self, line: LineIndex) -> i64 {
+        let num_parts = self.num_parts[line] as usize;
+        let mut weights = [0i64; MAX_PARTS];
+        for &turn in &self.visited_turns_per_line[line.as_usize()] {
+            for (w, &c) in weights
+                .iter_mut()
+                .zip(&self.visit_counts[turn as usize][line])
+            {
+                *w += c as i64;
+            }
+        }
+        let costs = &self.line_costs[line][..num_parts];
+        let weight_sum: i64 = weights[..num_parts].iter().sum();
+        if weight_sum == 0 {
+            return costs.iter().map(|&c| c as i64).sum::<i64>() / num_parts as i64;
+        }
+        let cost_sum: i64 = weights.iter().zip(costs).map(|(&w, &c)| w * c as i64).sum();
+        (cost_sum + weight_sum / 2) / weight_sum
+    }
+
+    // Total cost change per turn when each of `lines` is tied to its merged cost
+    fn tie_cost_diffs(&self, lines: &[(LineIndex, i64)]) -> FxHashMap<usize, i64> {
+        let mut cost_diffs = FxHashMap::default();
+        for &(line, merged_cost) in lines {
+            for &turn in &self.visited_turns_per_line[line.as_usize()] {
+                let counts = &self.visit_counts[turn as usize][line];
+                let diff: i64 = (0..self.num_parts[line] as usize)
+                    .map(|p| counts[p] as i64 * (merged_cost - self.line_costs[line][p] as i64))
+                    .sum();
+                *cost_diffs.entry(turn as usize).or_insert(0) += diff;
+            }
+        }
+        cost_diffs
+    }
+
+    /// Ties every part of `lines` to a single part at the merged cost
+    /// if that does not raise the loss by more than the penalty it saves.
+    fn try_tie_lines(&mut self, lines: &[LineIndex]) -> bool {
+        let lines: Vec<(LineIndex, i64)> = lines
+            .iter()
+            .filter(|&&line| self.num_parts[line] > 1)
+            .map(|&line| (line, self.merged_cost(line)))
+            .collect();
+        if lines.is_empty() {
+            return false;
+        }
+        let removed_parts: i64 = lines
+            .iter()
+            .map(|&(line, _)| self.num_parts[line] as i64 - 1)
+            .sum();
+        let cost_diffs = self.tie_cost_diffs(&lines);
+        let loss_diff = self.loss_diff_of_turns(&cost_diffs);
+        if loss_diff >= self.part_penalty * removed_parts {
+            return false;
+        }
+
+        for &(line, merged_cost) in &lines {
+            let num_parts = self.num_parts[line] as usize;
+            for &turn in &self.visited_turns_per_line[line.as_usize()] {
+                let counts = &mut self.visit_counts[turn as usize][line];
+                counts[0] = counts[..num_parts].iter().sum();
+                for c in &mut counts[1..] {
+                    *c = 0;
                 }
-                let prob = (-loss_diff as f64 / temp).exp();
-                if rng.gen::<f64>() < prob {
-                    if let Some(turns) = self.visited_turns_per_line.get(&line) {
-                        for &turn in turns {
-                            let turn = turn as usize;
-                            if !self.records[turn].visited.contains(&edge) {
-                                continue;
-                            }
-
-                            let cur_total_cost = self.total_costs[turn as usize] as i64;
-                            let cost_diff = self.line_costs[line][new_part] as i64
-                                - self.line_costs[line][old_part] as i64;
-                            let new_total_cost: u32 =
-                                (cur_total_cost + cost_diff).try_into().unwrap();
-
-                            assert!(
-                                self.visit_counts[turn][line][old_part] > 0,
-                                "{:?} {}",
-                                self.visit_counts[turn][line],
-                                self.mid_x[line],
-                            );
-                            self.visit_counts[turn][line][old_part] -= 1;
-                            self.visit_counts[turn][line][new_part] += 1;
-
-                            self.total_costs[turn] = new_total_cost;
-                        }
-                    }
-                    self.mid_x[line] = next_mid_x;
-                    self.loss += loss_diff;
-                    updates_type1 += 1;
+            }
+            self.line_costs[line] = [self.params.line_cost_lb as u32; MAX_PARTS];
+            self.line_costs[line][0] = merged_cost as u32;
+            self.part_starts[line] = [0; MAX_PARTS];
+            self.num_parts[line] = 1;
+        }
+        self.apply_cost_diffs(cost_diffs);
+        self.loss += loss_diff;
+        true
+    }
+
+    // BIC-style penalty of one extra part (a cost and a breakpoint) in units of the loss.
+    // `loss / n` only estimates the noise variance for the L2 loss, so other losses
+    // get no penalty.
+    fn compute_part_penalty(&self) -> i64 {
+        let n = self.records.len() as f64;
+        if self.params.split_selection == SplitSelection::Free
+            || self.params.loss != Loss::L2
+            || n < 2.0
+        {
+            return 0;
+        }
+        let noise = self.loss as f64 / n;
+        (self.params.split_penalty * 2.0 * n.ln() * noise) as i64
+    }
+
+    // Decides whether splits are justified after annealing
+    fn select_splits(&mut self) {
+        if self.params.min_parts > 1 {
+            return;
+        }
+        match self.params.split_selection {
+            SplitSelection::Free => {}
+            SplitSelection::Line => {
+                let tied = LineIndex::iter()
+                    .filter(|&line| self.try_tie_lines(&[line]))
+                    .count();
+                if tied > 0 {
+                    trace!("Tied {} lines", tied);
                 }
-            } else {
-                let edge = EdgeIndex::choose(&mut rng);
-                let sign: i64 = if rng.gen::<bool>() { 1 } else { -1 };
-                let cur_cost = self.edge_costs[edge];
-                let next_cost = cur_cost as i64 + sign * STEP;
-                if next_cost < EDGE_COST_LB || next_cost > EDGE_COST_UB {
-                    continue;
+            }
+            SplitSelection::Instance => {
+                let lines: Vec<LineIndex> = LineIndex::iter().collect();
+                if self.try_tie_lines(&lines) {
+                    trace!("Tied all lines");
                 }
+            }
+        }
+    }
 
-                let mut loss_diff = 0i64;
-                let mut loss_diff_updated = false;
+    /// Anneals for `time_share` of this turn's time budget at `temp_scale` times
+    /// the usual temperatures
+    pub(crate) fn update_estimation(&mut self, temp_scale: f64, time_share: f64) {
+        let start = Instant::now();
+        if self.params.reseed_per_turn {
+            let turn = self.records.len() as u64;
+            self.rng = SmallRng::seed_from_u64(self.params.seed ^ turn.wrapping_mul(TURN_SEED_MUL));
+        }
+        let time_limit = self
+            .scheduler
+            .turn_budget(self.records.len() - 1)
+            .mul_f64(time_share);
+        let max_loops = match self.params.budget {
+            Budget::Time => None,
+            Budget::IterationsPerTurn(n) => Some(n.max(1)),
+            Budget::TotalIterations(n) => Some((n / NUM_TURN as u64).max(1)),
+        };
+        // breakpoints are only added or removed when the number of parts may vary
+        let num_update_types = if self.params.min_parts < self.params.max_parts {
+            5
+        } else {
+            3
+        };
 
-                if let Some(turns) = self.visited_turns_per_edge.get(&edge) {
-                    for &turn in turns {
-                        let turn = turn as usize;
+        self.part_penalty = self.compute_part_penalty();
 
-                        let response = self.records[turn].response as i64;
-                        let cur_total_cost = self.total_costs[turn] as i64;
-                        let new_total_cost = self.total_costs[turn] as i64 + sign * STEP as i64;
-                        loss_diff -= (cur_total_cost - response).abs().pow(NORM_P);
-                        loss_diff += (new_total_cost - response).abs().pow(NORM_P);
-                        loss_diff_updated = true;
-                    }
-                }
+        let mut loops = 0u64;
+        let mut updates = [0u32; 8];
+        let start_loss = self.loss;
 
-                if !loss_diff_updated {
-                    continue;
-                }
+        loop {
+            let ratio = match max_loops {
+                Some(max_loops) => loops as f64 / max_loops as f64,
+                None => start.elapsed().as_secs_f64() / time_limit.as_secs_f64(),
+            };
 
-                let prob = (-loss_diff as f64 / temp).exp();
-                if rng.gen::<f64>() < prob {
-                    self.edge_costs[edge] = next_cost as i32;
-                    self.loss += loss_diff;
-                    if let Some(turns) = self.visited_turns_per_edge.get(&edge) {
-                        for &turn in turns {
-                            let new_total_cost =
-                                self.total_costs[turn as usize] as i64 + sign * STEP as i64;
-                            self.total_costs[turn as usize] = new_total_cost as u32;
-                        }
-                    }
-                    updates_type2 += 1;
-                }
+            if ratio >= 1.0 {
+                break;
             }
+
+            let temp = (self.params.start_temp
+                + (self.params.end_temp - self.params.start_temp) * ratio)
+                * temp_scale;
+
+            loops += 1;
+            let compound = self.params.compound_prob > 0.0
+                && self.rng.gen::<f64>() < self.params.compound_prob;
+            let update_type = if compound {
+                5 + self.rng.gen_range(0, 3)
+            } else {
+                self.rng.gen_range(0, num_update_types)
+            };
+            let updated = match update_type {
+                0 => self.move_line_cost(temp),
+                1 => self.move_breakpoint(temp),
+                2 => self.move_edge_cost(temp),
+                3 => self.add_breakpoint(temp),
+                4 => self.remove_breakpoint(temp),
+                5 => self.move_breakpoint_rebalance(temp),
+                6 => self.transfer_line_edge_cost(temp),
+                _ => self.trade_line_costs(temp),
+            };
+            if updated {
+                updates[update_type] += 1;
+            }
+        }
+
+        if num_update_types == 5 {
+            self.select_splits();
+        }
+        if self.params.adaptive_d {
+            self.update_edge_cost_bounds();
+        }
+
+        if max_loops.is_none() {
+            self.scheduler.record(start_loss as f64, self.loss as f64);
         }
 
         trace!(
-            "Finish updating estimation. loss={:6}->{:6}({:6}) loops={:4} updates=({:3}, {:3}, {:3})",
+            "Finish updating estimation. loss={:6}->{:6}({:6}) loops={:4} updates={:?} steps={:?}",
             start_loss,
             self.loss,
             self.loss - start_loss,
             loops,
-            updates_type0,
-            updates_type1,
-            updates_type2
+            updates,
+            self.steps
+        );
+        trace!(
+            "costs={:?} num_parts={:?} part_starts={:?}",
+            self.line_costs,
+            self.num_parts,
+            self.part_starts
         );
-        trace!("costs={:?} mid_x={:?}", self.line_costs, self.mid_x);
     }
 }
 
-pub fn run_solver<E: Environment>(env: &mut E, time_limit: Duration) {
-    let mut estimator = GraphEstimator::new(time_limit);
+pub fn run_solver<C: CostEstimator, E: Environment>(env: &mut E, params: &SolverParams) -> C {
+    resume_solver(env, params, C::new(params), 0)
+}
+
+/// Continues with an `estimator` that has already seen the first `turn`
+/// queries of `env`, e.g. one restored from a snapshot
+pub fn resume_solver<C: CostEstimator, E: Environment>(
+    env: &mut E,
+    params: &SolverParams,
+    mut estimator: C,
+    turn: usize,
+) -> C {
+    let mut selector = PathSelector::new(params);
+    selector.set_turn(turn);
     while let Some(query) = env.next_query() {
         trace!(
             "Start processing a query ({:2}, {:2}) -> ({:2}, {:2}) width={:2} height={:2}",
@@ -362,7 +1314,7 @@ pub fn run_solver<E: Environment>(env: &mut E, time_limit: Duration) {
             query.width(),
             query.height()
         );
-        let (path, estimated_length) = compute_shortest_path(&estimator, query.src, query.dest);
+        let (path, estimated_length) = selector.select(&estimator, &query);
         trace!(
             "Sending a path: {}",
             path.iter().map(|d| d.to_char()).collect::<String>()
@@ -378,8 +1330,127 @@ pub fn run_solver<E: Environment>(env: &mut E, time_limit: Duration) {
 
         #[cfg(feature = "log")]
         estimator.validate_cache();
+
+        if let Some(oracle) = env.oracle() {
+            let error = EstimationError::measure(oracle, &estimator);
+            trace!("Estimation error: {:?}", error);
+            env.record_estimation_error(error);
+        }
+    }
+    estimator.log_summary();
+    estimator
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::simulator::Simulator;
+
+    /// Vertical moves first, then horizontal ones
+    pub(crate) fn l_path(query: &Query) -> Vec<Dir> {
+        let (src, dest) = (query.src, query.dest);
+        let mut path = Vec::new();
+        for _ in 0..(src.r as i32 - dest.r as i32).abs() {
+            path.push(if src.r < dest.r { Dir::Down } else { Dir::Up });
+        }
+        for _ in 0..(src.c as i32 - dest.c as i32).abs() {
+            path.push(if src.c < dest.c {
+                Dir::Right
+            } else {
+                Dir::Left
+            });
+        }
+        path
+    }
+
+    /// Parameters annealing a few iterations per turn
+    pub(crate) fn test_params() -> SolverParams {
+        SolverParams {
+            budget: Budget::IterationsPerTurn(100),
+            ..SolverParams::default()
+        }
+    }
+
+    /// `estimator` after answering the first `turns` queries of seed 1 with `l_path`
+    pub(crate) fn answer_queries<C: CostEstimator>(estimator: &mut C, turns: usize) {
+        let mut simulator = Simulator::from_seed(1);
+        for _ in 0..turns {
+            let query = simulator.next_query().unwrap();
+            let path = l_path(&query);
+            let response = simulator.do_answer(&path);
+            estimator.insert_new_record(&query, &path, response);
+        }
+    }
+
+    fn assert_valid_parts(estimator: &GraphEstimator) {
+        for line in LineIndex::iter() {
+            let num_parts = estimator.num_parts[line] as usize;
+            assert!((1..=MAX_PARTS).contains(&num_parts));
+            let starts = &estimator.part_starts[line][..num_parts];
+            assert_eq!(starts[0], 0);
+            assert!(starts.windows(2).all(|w| w[0] < w[1]), "{:?}", starts);
+            assert!((starts[num_parts - 1] as usize) < GRID_LEN - 1);
+        }
+    }
+
+    fn total_parts(estimator: &GraphEstimator) -> usize {
+        LineIndex::iter()
+            .map(|line| estimator.num_parts[line] as usize)
+            .sum()
+    }
+
+    #[test]
+    fn add_and_remove_breakpoints() {
+        let params = SolverParams {
+            min_parts: 1,
+            max_parts: MAX_PARTS,
+            ..test_params()
+        };
+        let mut estimator = GraphEstimator::new(&params);
+        answer_queries(&mut estimator, 50);
+        assert_valid_parts(&estimator);
+
+        // a huge temperature accepts every move that is possible
+        let mut added = 0;
+        for _ in 0..500 {
+            let before = total_parts(&estimator);
+            let updated = estimator.add_breakpoint(1e18);
+            assert_eq!(total_parts(&estimator), before + updated as usize);
+            added += updated as usize;
+        }
+        assert!(added > 0);
+        assert_valid_parts(&estimator);
+        estimator.validate_cache();
+
+        let mut removed = 0;
+        for _ in 0..500 {
+            let before = total_parts(&estimator);
+            let updated = estimator.remove_breakpoint(1e18);
+            assert_eq!(total_parts(&estimator), before - updated as usize);
+            removed += updated as usize;
+        }
+        assert!(removed > 0);
+        assert_valid_parts(&estimator);
+        estimator.validate_cache();
+    }
+
+    #[test]
+    fn breakpoints_stay_within_part_range() {
+        let params = SolverParams {
+            min_parts: 2,
+            max_parts: 3,
+            ..test_params()
+        };
+        let mut estimator = GraphEstimator::new(&params);
+        answer_queries(&mut estimator, 20);
+        for _ in 0..1000 {
+            estimator.add_breakpoint(1e18);
+            estimator.remove_breakpoint(1e18);
+            for line in LineIndex::iter() {
+                assert!((2..=3).contains(&estimator.num_parts[line]));
+            }
+        }
+        assert_valid_parts(&estimator);
+        estimator.validate_cache();
     }
-    debug!("line_costs={:?}", estimator.line_costs);
-    debug!("edge_costs={:?}", estimator.edge_costs);
-    debug!("mid_x={:?}", estimator.mid_x);
 }
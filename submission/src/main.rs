@@ -1,7 +1,6 @@
-use std::time::Duration;
-
+use spq::params::SolverParams;
 use spq::remote::RemoteEnvironment;
-use spq::solver::run_solver;
+use spq::solver::{run_solver, GraphEstimator};
 
 fn main() {
     let stdin = std::io::stdin();
@@ -9,5 +8,5 @@ fn main() {
     let stdin = stdin.lock();
     let stdout = stdout.lock();
     let mut env = RemoteEnvironment::new(stdin, stdout);
-    run_solver(&mut env, Duration::from_secs(2));
+    run_solver::<GraphEstimator, _>(&mut env, &SolverParams::default());
 }
@@ -2,10 +2,9 @@ use clap::Clap;
 use env_logger::Env;
 use log::info;
 use spq::simulator::Simulator;
-use spq::solver::run_solver;
 use std::sync::mpsc;
-use std::time::Duration;
 use std::time::Instant;
+use tester::{run_estimator, SolverOptions};
 use threadpool::ThreadPool;
 
 /// Evaluate solver by multiple test cases
@@ -20,9 +19,8 @@ struct Arguments {
     #[clap(short, long, default_value = "5")]
     concurrency: usize,
 
-    /// time limit in msec
-    #[clap(short, long, default_value = "2000")]
-    time_limit: u64,
+    #[clap(flatten)]
+    solver: SolverOptions,
 }
 
 fn mean(data: &[f64]) -> f64 {
@@ -51,17 +49,23 @@ fn main() -> Result<(), mpsc::RecvError> {
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
 
     let args = Arguments::parse();
+    let params = args
+        .solver
+        .solver_params()
+        .unwrap_or_else(|e| panic!("invalid solver params: {}", e));
+    info!("params: {:?}", params);
     let pool = ThreadPool::new(args.concurrency);
 
     let (tx, rx) = mpsc::channel();
 
     for seed in 0..args.num {
         let tx = tx.clone();
-        let time_limit = args.time_limit;
+        let params = params.clone();
+        let estimator = args.solver.estimator.clone();
         pool.execute(move || {
             let mut simulator = Simulator::from_seed(seed);
             let start = Instant::now();
-            run_solver(&mut simulator, Duration::from_millis(time_limit));
+            run_estimator(&estimator, &mut simulator, &params);
             tx.send((seed, simulator, start.elapsed()))
                 .expect("failed to send");
         });
@@ -89,7 +93,8 @@ fn main() -> Result<(), mpsc::RecvError> {
 
     info!("n:        {:}", args.num);
     info!("c:        {:}", args.concurrency);
-    info!("t:        {:}ms", args.time_limit);
+    info!("t:        {:}ms", params.time_limit.as_millis());
+    info!("e:        {:}", args.solver.estimator);
     info!("max_time: {:}ms", max_elapsed);
     info!("mean:     {:.6}", mean(&ratio_scores));
     info!("sd:       {:.6}", std_deviation(&ratio_scores));
@@ -0,0 +1,122 @@
+use clap::Clap;
+use env_logger::Env;
+use log::info;
+use spq::models::*;
+use spq::simulator::Simulator;
+use std::fs::File;
+use std::io::{self, Write};
+
+/// Fit priors of generated instances and print them as Rust source for spq/src/priors.rs
+#[derive(Clap, Debug)]
+#[clap(name = "train_priors")]
+struct Arguments {
+    /// the number of generated instances
+    #[clap(short, long, default_value = "10000")]
+    num: u64,
+
+    /// output file (stdout if omitted)
+    #[clap(short, long)]
+    output: Option<String>,
+}
+
+const LINE_COST_MIN: usize = 1000;
+const LINE_COST_BIN: usize = 500;
+const LINE_COST_BINS: usize = 16;
+
+fn normalize(counts: &[u64]) -> Vec<f64> {
+    let total: u64 = counts.iter().sum();
+    counts
+        .iter()
+        .map(|&c| c as f64 / total.max(1) as f64)
+        .collect()
+}
+
+fn format_array(values: &[f64]) -> String {
+    let values: Vec<String> = values.iter().map(|v| format!("{:.6}", v)).collect();
+    format!("[{}]", values.join(", "))
+}
+
+fn main() -> io::Result<()> {
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+
+    let args = Arguments::parse();
+
+    let mut doubles = 0u64;
+    let mut line_costs = vec![0u64; LINE_COST_BINS];
+    let mut line_cost_sum = 0u64;
+    let mut line_cost_count = 0u64;
+    let mut breakpoints = vec![0u64; GRID_LEN - 1];
+    let mut breakpoint_sum = 0u64;
+    let mut breakpoint_count = 0u64;
+
+    for seed in 0..args.num {
+        let simulator = Simulator::from_seed(seed);
+        let params = simulator.graph_params();
+        if params.graph_type() == "double" {
+            doubles += 1;
+        }
+        for line in LineIndex::iter() {
+            for (x, cost) in params.line_parts(line) {
+                let cost = cost as usize;
+                line_costs[((cost - LINE_COST_MIN) / LINE_COST_BIN).min(LINE_COST_BINS - 1)] += 1;
+                line_cost_sum += cost as u64;
+                line_cost_count += 1;
+                if x > 0 {
+                    breakpoints[x as usize] += 1;
+                    breakpoint_sum += x as u64;
+                    breakpoint_count += 1;
+                }
+            }
+        }
+    }
+    info!("generated {} instances", args.num);
+    // only the priors read by the solver are written to the source
+    info!("double ratio: {:.6}", doubles as f64 / args.num as f64);
+    info!(
+        "line costs: bins of {} from {}: {}",
+        LINE_COST_BIN,
+        LINE_COST_MIN,
+        format_array(&normalize(&line_costs))
+    );
+    info!(
+        "breakpoint mean: {:.3}",
+        breakpoint_sum as f64 / breakpoint_count.max(1) as f64
+    );
+
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    writeln!(
+        out,
+        "//! Priors of generated instances. Generated by `train_priors --num {}`, do not edit.",
+        args.num
+    )?;
+    writeln!(out, "use crate::models::GRID_LEN;")?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "/// Mean base cost of a part. Costs are symmetric over their range, so this is"
+    )?;
+    writeln!(
+        out,
+        "/// about its midpoint and carries little information."
+    )?;
+    writeln!(
+        out,
+        "pub const LINE_COST_MEAN: f64 = {:.3};",
+        line_cost_sum as f64 / line_cost_count as f64
+    )?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "/// Distribution of the first x of the second part, by x. Nearly uniform"
+    )?;
+    writeln!(out, "/// over 1..GRID_LEN - 1.")?;
+    writeln!(
+        out,
+        "pub const BREAKPOINT_HISTOGRAM: [f64; GRID_LEN - 1] = {};",
+        format_array(&normalize(&breakpoints))
+    )?;
+    Ok(())
+}
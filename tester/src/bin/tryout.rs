@@ -1,12 +1,14 @@
 use log::info;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
-use std::time::Duration;
 
 use clap::Clap;
 use spq::models::*;
+use spq::params::SolverParams;
 use spq::simulator::Simulator;
-use spq::solver::run_solver;
+use spq::snapshot::Snapshot;
+use spq::solver::{resume_solver, run_solver, CostEstimator, GraphEstimator};
+use tester::{format_graph, run_estimator, SolverOptions};
 
 use env_logger::Env;
 
@@ -20,12 +22,29 @@ struct Arguments {
     /// Output file for visualizer
     #[clap(short, long)]
     output: Option<String>,
-    /// time limit in msec
-    #[clap(short, long, default_value = "2000")]
-    time_limit: u64,
+    /// Save a snapshot of the graph estimator after this many turns. Needs
+    /// `-p reseed_per_turn=true`, as does --resume
+    #[clap(long, requires = "snapshot-out")]
+    snapshot_turn: Option<usize>,
+    /// Snapshot file written with --snapshot-turn
+    #[clap(long, requires = "snapshot-turn")]
+    snapshot_out: Option<String>,
+    /// Resume the graph estimator from a snapshot taken on the same seed
+    #[clap(long, conflicts_with = "snapshot-turn")]
+    resume: Option<String>,
+    /// Output file for the final estimated edge costs, laid out like the input
+    #[clap(long)]
+    estimate: Option<String>,
+    /// CSV output file for the estimation error after each turn. Errors are
+    /// only measured against the truth when this is given.
+    #[clap(long)]
+    error_out: Option<String>,
+    #[clap(flatten)]
+    solver: SolverOptions,
 }
 
-struct TryoutEnvironment(Simulator, Option<File>);
+// simulator, output file for the visualizer and, when measured, the estimation error of each turn
+struct TryoutEnvironment(Simulator, Option<File>, Option<Vec<EstimationError>>);
 
 impl Environment for TryoutEnvironment {
     fn next_query(&self) -> Option<Query> {
@@ -40,19 +59,150 @@ impl Environment for TryoutEnvironment {
             )
             .expect("write failed");
         }
-        self.0.do_answer(&path)
+        self.0.do_answer(path)
     }
+    fn oracle(&self) -> Option<&dyn Oracle> {
+        match self.2 {
+            Some(_) => Some(&self.0),
+            None => None,
+        }
+    }
+    fn record_estimation_error(&mut self, error: EstimationError) {
+        if let Some(errors) = &mut self.2 {
+            errors.push(error);
+        }
+    }
+}
+
+/// Ends after `remaining` more queries of the wrapped environment
+struct TruncatedEnvironment<'a, E: Environment> {
+    env: &'a mut E,
+    remaining: usize,
+}
+
+impl<'a, E: Environment> Environment for TruncatedEnvironment<'a, E> {
+    fn next_query(&self) -> Option<Query> {
+        if self.remaining == 0 {
+            None
+        } else {
+            self.env.next_query()
+        }
+    }
+    fn do_answer(&mut self, path: &[Dir]) -> u32 {
+        self.remaining -= 1;
+        self.env.do_answer(path)
+    }
+    fn oracle(&self) -> Option<&dyn Oracle> {
+        self.env.oracle()
+    }
+    fn record_estimation_error(&mut self, error: EstimationError) {
+        self.env.record_estimation_error(error)
+    }
+}
+
+fn run_with_snapshot(
+    env: &mut TryoutEnvironment,
+    params: &SolverParams,
+    turn: usize,
+    out: &str,
+) -> GridGraph<u32> {
+    let estimator: GraphEstimator = run_solver(
+        &mut TruncatedEnvironment {
+            env,
+            remaining: turn,
+        },
+        params,
+    );
+    fs::write(out, estimator.snapshot().to_text()).expect("failed to write snapshot");
+    info!("Saved a snapshot after turn {} to {}", turn, out);
+    resume_solver(env, params, estimator, turn).estimated_graph()
+}
+
+fn resume_from_snapshot(
+    env: &mut TryoutEnvironment,
+    params: &SolverParams,
+    path: &str,
+) -> GridGraph<u32> {
+    let text = fs::read_to_string(path).expect("failed to read snapshot");
+    let snapshot: Snapshot = text
+        .parse()
+        .unwrap_or_else(|e| panic!("invalid snapshot: {}", e));
+    // bring the simulator to the same turn
+    for record in &snapshot.records {
+        let query = env.next_query().expect("snapshot has too many turns");
+        assert!(
+            query.src == record.query.src && query.dest == record.query.dest,
+            "snapshot was taken on another test case"
+        );
+        let response = env.do_answer(&record.path);
+        assert_eq!(
+            response, record.response,
+            "snapshot was taken on another test case"
+        );
+    }
+    let estimator = GraphEstimator::from_snapshot(&snapshot, params)
+        .unwrap_or_else(|e| panic!("failed to restore snapshot: {}", e));
+    info!("Resuming from turn {}", snapshot.turn());
+    resume_solver(env, params, estimator, snapshot.turn()).estimated_graph()
+}
+
+fn format_errors(errors: &[EstimationError]) -> String {
+    fn optional(value: Option<f64>) -> String {
+        value.map(|v| format!("{:.3}", v)).unwrap_or_default()
+    }
+    let mut text = "turn,edge_rmse,line_base_rmse,breakpoint_rmse\n".to_string();
+    for (turn, e) in errors.iter().enumerate() {
+        text += &format!(
+            "{},{:.3},{},{}\n",
+            turn + 1,
+            e.edge_rmse,
+            optional(e.line_base_rmse),
+            optional(e.breakpoint_rmse)
+        );
+    }
+    text
 }
 
 fn main() {
     env_logger::Builder::from_env(Env::default().default_filter_or("debug")).init();
 
     let args = Arguments::parse();
+    let params = args
+        .solver
+        .solver_params()
+        .unwrap_or_else(|e| panic!("invalid solver params: {}", e));
 
     let file = args.output.map(|s| File::create(s).unwrap());
 
-    let mut env = TryoutEnvironment(Simulator::from_seed(args.seed), file);
-    run_solver(&mut env, Duration::from_millis(args.time_limit));
+    let errors = args.error_out.as_ref().map(|_| Vec::new());
+    let mut env = TryoutEnvironment(Simulator::from_seed(args.seed), file, errors);
+    let uses_snapshot = args.snapshot_turn.is_some() || args.resume.is_some();
+    assert!(
+        !uses_snapshot || args.solver.estimator == "graph",
+        "snapshots are only supported by the graph estimator"
+    );
+    // the generator state is not saved, so only reseeding every turn continues exactly
+    assert!(
+        !uses_snapshot || params.reseed_per_turn,
+        "snapshots need -p reseed_per_turn=true to reproduce the run"
+    );
+    let estimate = if let (Some(turn), Some(out)) = (args.snapshot_turn, &args.snapshot_out) {
+        run_with_snapshot(&mut env, &params, turn, out)
+    } else if let Some(path) = &args.resume {
+        resume_from_snapshot(&mut env, &params, path)
+    } else {
+        run_estimator(&args.solver.estimator, &mut env, &params)
+    };
+    if let Some(path) = &args.estimate {
+        fs::write(path, format_graph(&estimate)).expect("failed to write estimate");
+    }
+
+    if let (Some(path), Some(errors)) = (&args.error_out, &env.2) {
+        fs::write(path, format_errors(errors)).expect("failed to write estimation errors");
+        if let Some(error) = errors.last() {
+            info!("final error: {:?}", error);
+        }
+    }
 
     let simulator = &env.0;
 
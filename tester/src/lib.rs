@@ -0,0 +1,94 @@
+use clap::Clap;
+use spq::bayes::BayesEstimator;
+use spq::ensemble::EnsembleEstimator;
+use spq::least_squares::LeastSquaresEstimator;
+use spq::models::*;
+use spq::multichain::MultiChainEstimator;
+use spq::params::SolverParams;
+use spq::solver::{run_solver, CostEstimator, GraphEstimator};
+use std::fs;
+use std::time::Duration;
+
+/// Names accepted by `--estimator`
+pub const ESTIMATORS: &[&str] = &["graph", "least_squares", "bayes", "multichain", "ensemble"];
+
+// Solver options shared by the tester binaries (not a doc comment, which
+// would replace the about text of the binaries)
+#[derive(Clap, Debug)]
+pub struct SolverOptions {
+    /// time limit in msec [default: 2000]. Overrides --params and -p
+    #[clap(short, long)]
+    pub time_limit: Option<u64>,
+
+    /// cost estimator used by the solver
+    #[clap(short, long, default_value = "graph", possible_values = ESTIMATORS)]
+    pub estimator: String,
+
+    /// file with solver parameters (`key = value` per line)
+    #[clap(long)]
+    pub params: Option<String>,
+
+    /// override a solver parameter (e.g. `-p step=30`). Applied after --params
+    #[clap(short = 'p', long = "param")]
+    pub overrides: Vec<String>,
+}
+
+impl SolverOptions {
+    pub fn solver_params(&self) -> Result<SolverParams, String> {
+        let mut params = SolverParams::default();
+        if let Some(path) = &self.params {
+            let text =
+                fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+            params.update_from_str(&text)?;
+        }
+        for pair in &self.overrides {
+            params.set_pair(pair)?;
+        }
+        if let Some(time_limit) = self.time_limit {
+            params.time_limit = Duration::from_millis(time_limit);
+        }
+        params.validate()?;
+        Ok(params)
+    }
+}
+
+/// Run the solver on `env` with the estimator called `name` and return its final estimate
+pub fn run_estimator<E: Environment>(
+    name: &str,
+    env: &mut E,
+    params: &SolverParams,
+) -> GridGraph<u32> {
+    match name {
+        "graph" => run_solver::<GraphEstimator, _>(env, params).estimated_graph(),
+        "least_squares" => run_solver::<LeastSquaresEstimator, _>(env, params).estimated_graph(),
+        "bayes" => run_solver::<BayesEstimator, _>(env, params).estimated_graph(),
+        "multichain" => run_solver::<MultiChainEstimator, _>(env, params).estimated_graph(),
+        "ensemble" => run_solver::<EnsembleEstimator, _>(env, params).estimated_graph(),
+        _ => panic!("unknown estimator: {}", name),
+    }
+}
+
+/// Edge costs in the layout of the input: `GRID_LEN` rows of horizontal
+/// edges followed by `GRID_LEN - 1` rows of vertical edges
+pub fn format_graph(graph: &GridGraph<u32>) -> String {
+    let mut text = String::new();
+    for r in 0..GRID_LEN {
+        let line = LineIndex::new(Axis::Horizontal, r as u8);
+        let row: Vec<String> = (0..GRID_LEN - 1)
+            .map(|c| graph[EdgeIndex::new(line, c as u8)].to_string())
+            .collect();
+        text += &row.join(" ");
+        text.push('\n');
+    }
+    for r in 0..GRID_LEN - 1 {
+        let row: Vec<String> = (0..GRID_LEN)
+            .map(|c| {
+                let line = LineIndex::new(Axis::Vertical, c as u8);
+                graph[EdgeIndex::new(line, r as u8)].to_string()
+            })
+            .collect();
+        text += &row.join(" ");
+        text.push('\n');
+    }
+    text
+}